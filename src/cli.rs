@@ -0,0 +1,138 @@
+//! Subcommands for running operations tasks without a Discord client.
+
+use std::num::NonZeroU64;
+
+use anyhow::{ensure, Context as ContextTrait, Result};
+use clap::{Parser, Subcommand};
+use serenity::http::{Http, HttpBuilder};
+use serenity::model::prelude::{GuildId, UserId};
+
+use crate::commands::api::{self, ApiError};
+use crate::commands::ApiClient;
+use crate::config::Config;
+use crate::registration::{purge_guild_commands, Scope};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Verifies University of Southampton students in Discord servers."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the bot, this is the default.
+    Run,
+    /// Syncs the slash commands with Discord.
+    RegisterCommands {
+        /// Register the commands in this guild rather than globally, defaults to TEST_GUILD_ID.
+        #[arg(long)]
+        guild: Option<NonZeroU64>,
+    },
+    /// Removes every slash command registered in the given guilds, defaults to PURGE_GUILD_IDS.
+    PurgeCommands { guilds: Vec<NonZeroU64> },
+    /// Shows whether a user is verified and the verified role of a guild.
+    CheckUser { user: NonZeroU64, guild: NonZeroU64 },
+    /// Checks the configuration, the Discord token and the API key.
+    ConfigCheck,
+}
+
+/// Creates a Discord client for the subcommands that manage slash commands.
+pub async fn discord(config: &Config) -> Result<Http> {
+    connect(HttpBuilder::new(&config.discord_token)).await
+}
+
+/// Builds the client and sets its application id, which serenity needs for every command route.
+/// The gateway client learns it from the ready event but these subcommands never connect.
+pub async fn connect(builder: HttpBuilder) -> Result<Http> {
+    let http = builder.build();
+    let app = http
+        .get_current_application_info()
+        .await
+        .context("Unable to get the application, is DISCORD_TOKEN correct?")?;
+    http.set_application_id(app.id);
+    Ok(http)
+}
+
+pub async fn register_commands(
+    http: &Http,
+    config: &Config,
+    guild: Option<NonZeroU64>,
+) -> Result<()> {
+    let scope = match guild.map(GuildId::from).or(config.test_guild_id) {
+        Some(guild_id) => Scope::Guild(guild_id),
+        None => Scope::Global,
+    };
+    for (scope, summary) in crate::register_commands(http, config, scope).await? {
+        println!("Synced {scope:?} slash commands: {summary}");
+    }
+    Ok(())
+}
+
+pub async fn purge_commands(http: &Http, config: &Config, guilds: Vec<NonZeroU64>) -> Result<()> {
+    let guilds = if guilds.is_empty() {
+        config.purge_guild_ids.clone()
+    } else {
+        guilds.into_iter().map(GuildId::from).collect()
+    };
+    if guilds.is_empty() {
+        println!("No guilds to purge, pass some or set PURGE_GUILD_IDS.");
+    }
+    for guild_id in guilds {
+        let summary = purge_guild_commands(http, guild_id)
+            .await
+            .context(format!("Unable to purge commands from guild {guild_id}."))?;
+        println!("Purged guild {guild_id} slash commands: {summary}");
+    }
+    Ok(())
+}
+
+pub async fn check_user(config: &Config, user: NonZeroU64, guild: NonZeroU64) -> Result<()> {
+    let api = ApiClient::new(config)?;
+    let (user_id, guild_id) = (UserId::from(user), GuildId::from(guild));
+    match api::is_verified(&api, user_id, guild_id).await {
+        Ok(()) => println!("User {user_id} is verified."),
+        Err(ApiError::NotVerified) => println!("User {user_id} is not verified."),
+        Err(e) => return Err(e).context("Unable to check if the user is verified."),
+    }
+    match api::get_guild(&api, guild_id).await {
+        Ok(guild) => println!(
+            "Guild {guild_id} gives role {} and is {}.",
+            guild.role_id,
+            if guild.approved {
+                "approved"
+            } else {
+                "not approved"
+            }
+        ),
+        Err(ApiError::GuildNotRegistered) => println!("Guild {guild_id} is not registered."),
+        Err(e) => return Err(e).context("Unable to get the guild."),
+    }
+    Ok(())
+}
+
+pub async fn config_check(config: &Config) -> Result<()> {
+    println!("Configuration is valid.");
+    ensure!(
+        !config.data_dir.is_file(),
+        "DATA_DIR {} is a file rather than a directory.",
+        config.data_dir.display()
+    );
+    println!("Files are kept in {}.", config.data_dir.display());
+    let user = Http::new(&config.discord_token)
+        .get_current_user()
+        .await
+        .context("DISCORD_TOKEN was rejected by Discord.")?;
+    println!("Discord token belongs to {}.", user.name);
+
+    let api = ApiClient::new(config)?;
+    // Any guild will do, we only care whether the API accepts our key.
+    match api::get_guild(&api, GuildId::new(1)).await {
+        Ok(_) | Err(ApiError::GuildNotRegistered) => println!("API key was accepted."),
+        Err(e) => return Err(e).context("Unable to reach the verify API."),
+    }
+    Ok(())
+}
@@ -1,12 +1,10 @@
-use std::env;
 use std::time::Duration;
 
 use anyhow::Result;
 use anyhow::{anyhow, bail, ensure, Context as ContextTrait};
 use cached::Cached;
+use futures::join;
 use futures::stream::FuturesUnordered;
-use futures::{join, Stream};
-use log::warn;
 use reqwest::Url;
 use serenity::all::ActionRowComponent::InputText;
 use serenity::all::{
@@ -18,116 +16,308 @@ use serenity::builder::{
     CreateModal,
 };
 use serenity::client::Context;
-use serenity::collector::ModalInteractionCollector;
 use serenity::futures::StreamExt;
+use tokio::time::{interval_at, Instant};
+use tracing::{info, warn, Instrument};
 
-use serenity::model::guild::{PartialGuild, Role};
-use serenity::model::prelude::{GuildId, UserId};
+use serenity::model::guild::{Member, PartialGuild, Role};
+use serenity::model::prelude::{GuildId, RoleId, UserId};
 
-use crate::commands::api::{register_guild, RegisterParams};
-use crate::TASK_LIST;
+use crate::commands::api::{register_guild, ApiError, RegisterParams, Status};
+use crate::commands::cleanup::alert_admins;
+use crate::commands::modals::wait_for_modal;
+use crate::commands::ratelimit::{VERIFY_GUILD_LIMIT, VERIFY_USER_LIMIT};
+use crate::commands::roles::{change_roles, RoleChange};
+use crate::history::{self, Method};
+use crate::scheduler::Task;
+use crate::state::state;
 
-mod api;
+pub use api::ApiClient;
+pub use audit::audit;
+pub use cleanup::{guild_left, role_deleted, role_updated};
+pub use components::deliver_component;
+pub use context_menu::{check_verification, verify_user};
+pub use diagnose::diagnose;
+pub use error::RetryPolicy;
+pub use export::export;
+pub use lockdown::lockdown;
+pub use modals::deliver_modal;
+pub use operator::operator;
+pub use stats::stats;
+pub use whois::whois;
+
+pub mod api;
+mod audit;
+pub mod cleanup;
+mod components;
+mod context_menu;
+mod diagnose;
+mod error;
+pub mod export;
+mod link;
+mod lockdown;
+mod modals;
+mod operator;
+pub mod ratelimit;
+mod roles;
+pub mod stats;
+mod whois;
 
 pub async fn verify(ctx: &Context, command: CommandInteraction) -> Result<()> {
     let guild_id = command.guild_id.unwrap();
-    match api::get_role_id(guild_id)
+    let state = state(ctx).await;
+    let config = state.config();
+    let limited = VERIFY_USER_LIMIT
+        .try_acquire(command.user.id, config.verify_user_limit)
+        .and_then(|()| {
+            VERIFY_GUILD_LIMIT
+                .try_acquire(guild_id, config.verify_guild_limit)
+                .inspect_err(|_| VERIFY_USER_LIMIT.release(&command.user.id))
+        });
+    if let Err(wait) = limited {
+        command
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content(format!(
+                            "Slow down! Please try again in {}s.",
+                            wait.as_secs_f64().ceil()
+                        ))
+                        .ephemeral(true),
+                ),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        return Ok(());
+    }
+    // Adding the role waits behind the guild's other role changes, which can take longer than
+    // Discord allows before the first response.
+    command
+        .defer_ephemeral(ctx)
         .await
-        .context(concat!(file!(), ":", line!()))
-    {
-        Ok(role) => {
-            if let Err(e) = api::is_verified(command.user.id, guild_id)
+        .context(concat!(file!(), ":", line!()))?;
+    let guild = match api::get_guild(&state.api(), guild_id).await {
+        Ok(guild) => guild,
+        Err(e) => {
+            command
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new().content(e.user_message()),
+                )
                 .await
-                .context(concat!(file!(), ":", line!()))
-            {
-                TASK_LIST
-                    .get()
-                    .expect("OnceCell should be instantiated")
-                    .send((command.user.id, guild_id))
-                    .ok();
-
-                command
-                        .create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(format!(
-                            "Please verify yourself by going to {} and then run this command again.",
-                            env::var("DISPLAY_URL")
-                                .expect("DISPLAY_URL environment var has not been set")
-                        )).ephemeral(true)))
-                        .await
-                        .context(concat!(file!(), ":", line!()))?;
-                return Err(e);
-            }
+                .context(concat!(file!(), ":", line!()))?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
 
-            match ctx
-                .http
-                .add_member_role(command.guild_id.unwrap(), command.user.id, role, None)
+    if let Err(e) = api::is_verified(&state.api(), command.user.id, guild_id).await {
+        if e.retry_policy() != RetryPolicy::Never {
+            state
+                .tasks
+                .send(Task::Verify(command.user.id, guild_id, Method::Command))
+                .ok();
+        }
+
+        let content = match e {
+            ApiError::NotVerified => format!(
+                "Please verify yourself by going to {} and then run this command again.",
+                link::verification_url(&state.config(), command.user.id, guild_id)
+            ),
+            ref e => e.user_message(),
+        };
+        command
+            .edit_response(ctx, EditInteractionResponse::new().content(content))
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        return match e {
+            ApiError::NotVerified => Ok(()),
+            e => Err(e).context(concat!(file!(), ":", line!())),
+        };
+    }
+
+    match add_verified_role(ctx, guild_id, command.user.id, guild)
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        Ok(_) => {
+            history::record_verified(guild_id, &[command.user.id], Method::Command).await;
+            command
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new().content("You have now been verified!"),
+                )
                 .await
-                .context(concat!(file!(), ":", line!()))
-            {
-                Ok(_) => {
-                    command
-                        .create_response(
-                            ctx,
-                            CreateInteractionResponse::Message(
-                                CreateInteractionResponseMessage::new()
-                                    .content("You have now been verified!")
-                                    .ephemeral(true),
-                            ),
-                        )
-                        .await
-                        .context(concat!(file!(), ":", line!()))?;
-                    Ok(())
-                }
-                Err(e) => {
-                    command
-                        .create_response(ctx,  CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content("I was unable to add the verified role, please make sure my role has higher permissions than the verified role.")))
-                        .await.context(concat!(file!(), ":", line!()))?;
-                    Err(e).context("Could not add verified role.")
-                }
-            }
+                .context(concat!(file!(), ":", line!()))?;
+            Ok(())
         }
         Err(e) => {
             command
-                .create_response(ctx,  CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content("It looks like your server doesn't support this bot, please contact the admins so they can run /setup."))
-                )
+                .edit_response(ctx, EditInteractionResponse::new().content("I was unable to add the verified role, please make sure my role has higher permissions than the verified role."))
                 .await.context(concat!(file!(), ":", line!()))?;
-            Err(e)
+            Err(e).context("Could not add verified role.")
         }
     }
 }
 
+/// How many members to check with each batch verification request.
+const BATCH_SIZE: usize = 100;
+
+/// How often /verify-all shows how far it has got.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a command's response can be edited for, a little under the 15 minutes its
+/// interaction token lasts.
+const RESPONSE_DEADLINE: Duration = Duration::from_secs(14 * 60);
+
 /// Re-verifies an entire server (This only adds verified people), also invalidates guild role cache
+///
+/// If the server uses an unverified role it is also reconciled, verified members lose it and
+/// everyone who couldn't be verified is given it.
 pub async fn verify_all(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let started = Instant::now();
     let guild_id = command.guild_id.unwrap();
+    let api = state(ctx).await.api();
     {
-        let mut cache = api::GET_ROLE_ID.lock().await;
+        let mut cache = api::GET_GUILD.lock().await;
         cache.cache_remove(&guild_id);
     }
-    let (defer, role_id) = join!(command.defer(ctx), api::get_role_id(guild_id));
+    let (defer, guild) = join!(command.defer(ctx), api::get_guild(&api, guild_id));
     defer.context(concat!(file!(), ":", line!()))?;
-    match role_id.context(concat!(file!(), ":", line!())) {
-        Ok(role) => {
-            let mut members = guild_id
+    match guild {
+        Ok(guild) => {
+            let mut chunks = guild_id
                 .members_iter(ctx)
                 .filter_map(move |r| async { r.ok() })
+                .chunks(BATCH_SIZE)
                 .boxed();
             let mut unordered = FuturesUnordered::new();
-            while let Some(member) = members.next().await {
-                // Filter all the members that have the verified role or are a bot.
-                if !member.user.bot && !member.roles.iter().any(|r| r == &role) {
-                    let (guild_id, user_id) = (member.guild_id, member.user.id);
-                    unordered.push(silent_verify(ctx, user_id, guild_id));
+            let mut marking = FuturesUnordered::new();
+            let mut cleanup = FuturesUnordered::new();
+            let mut membership = FuturesUnordered::new();
+            let mut batch_supported = true;
+            while let Some(chunk) = chunks.next().await {
+                let mut unverified = vec![];
+                for member in chunk {
+                    // Filter all the members that have the verified role or are a bot, along with
+                    // graduates who have already been moved to the alumni role.
+                    if member.user.bot
+                        || guild
+                            .alumni_role_id
+                            .is_some_and(|r| member.roles.contains(&r))
+                    {
+                        continue;
+                    }
+                    if !member.roles.contains(&guild.role_id) {
+                        unverified.push(member);
+                        continue;
+                    }
+                    if let Some(unverified) = guild
+                        .unverified_role_id
+                        .filter(|r| member.roles.contains(r))
+                    {
+                        cleanup.push(change_roles(
+                            ctx,
+                            guild_id,
+                            RoleChange::Remove(member.user.id, unverified),
+                        ));
+                    }
+                    if let Some(member_role) = guild.member_role_id {
+                        let held = Some(member.roles.contains(&member_role));
+                        membership.push(sync_member_role(
+                            ctx,
+                            guild_id,
+                            member.user.id,
+                            guild,
+                            held,
+                        ));
+                    }
+                }
+                if unverified.is_empty() {
+                    continue;
+                }
+
+                if batch_supported {
+                    let user_ids = unverified.iter().map(|m| m.user.id).collect::<Vec<_>>();
+                    match api::batch_verified(&api, &user_ids, guild_id).await {
+                        Ok(Some(verified)) => {
+                            for member in unverified {
+                                if verified.contains(&member.user.id) {
+                                    // The batch call has cached them as verified.
+                                    unordered.push(verify_member(ctx, member));
+                                } else {
+                                    marking
+                                        .push(async move { mark_unverified(ctx, &member).await });
+                                }
+                            }
+                            continue;
+                        }
+                        Ok(None) => {
+                            info!("The verify service doesn't support batch lookups, checking members individually.");
+                            batch_supported = false;
+                        }
+                        Err(e) => warn!(
+                            "Could not batch verify members, checking them individually. {e:?}"
+                        ),
+                    }
                 }
+                unordered.extend(unverified.into_iter().map(|m| verify_member(ctx, m)));
             }
-            let mut num_verified = 0;
-            while let Some(verified) = unordered.next().await {
-                if verified.verified {
-                    num_verified += 1;
+            let total = unordered.len();
+            let mut checked = 0;
+            let mut verified_ids = vec![];
+            let mut progress = interval_at(started + PROGRESS_INTERVAL, PROGRESS_INTERVAL);
+            loop {
+                tokio::select! {
+                    verified = unordered.next() => {
+                        let Some(verified) = verified else {
+                            break;
+                        };
+                        checked += 1;
+                        if verified.verified {
+                            verified_ids.push(verified.user_id);
+                        }
+                    }
+                    _ = progress.tick(), if started.elapsed() < RESPONSE_DEADLINE => {
+                        let content = format!("Checked {checked} of {total} unverified members...");
+                        if let Err(e) = command
+                            .edit_response(ctx, EditInteractionResponse::new().content(content))
+                            .await
+                        {
+                            warn!("Could not show /verify-all progress. {e:?}");
+                        }
+                    }
+                }
+            }
+            history::record_verified(guild_id, &verified_ids, Method::VerifyAll).await;
+            let num_verified = verified_ids.len();
+            while marking.next().await.is_some() {}
+            while let Some(removed) = cleanup.next().await {
+                if let Err(e) = removed {
+                    warn!("Could not remove unverified role. {e:?}");
+                }
+            }
+            while let Some(synced) = membership.next().await {
+                if let Err(e) = synced {
+                    warn!("Could not update member role. {e:?}");
                 }
             }
             let members = match num_verified {
                 1 => "member",
                 _ => "members",
             };
+            if started.elapsed() >= RESPONSE_DEADLINE {
+                // The response can no longer be edited, so let the admins know another way.
+                alert_admins(
+                    ctx,
+                    guild_id,
+                    &format!(
+                        "/verify-all has finished. Was able to verify {num_verified} {members}."
+                    ),
+                )
+                .await;
+                return Ok(());
+            }
             command
                 .edit_response(ctx, EditInteractionResponse::new().content(format!("Successfully completed re-verifications. Was able to verify {num_verified} {members}.")))
                 .await
@@ -136,9 +326,13 @@ pub async fn verify_all(ctx: &Context, command: CommandInteraction) -> Result<()
         }
         Err(e) => {
             command
-                .edit_response(ctx, EditInteractionResponse::new().content("It looks like your server doesn't support this bot, please contact the admins."))
-                .await.context(concat!(file!(), ":", line!()))?;
-            Err(e)
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new().content(e.admin_message()),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            Err(e).context(concat!(file!(), ":", line!()))
         }
     }
 }
@@ -148,49 +342,194 @@ pub struct IsVerified {
     pub guild_id: GuildId,
     pub user_id: UserId,
     pub verified: bool,
+    /// What to do if the user couldn't be verified.
+    pub retry: RetryPolicy,
 }
 
 /// Verifies multiple users, any errors are just printed.
 pub async fn silent_verify(ctx: &Context, user_id: UserId, guild_id: GuildId) -> IsVerified {
-    match api::is_verified(user_id, guild_id)
-        .await
-        .context(concat!(file!(), ":", line!()))
-        .context(format!(
-            "Could not batch verify user with id {user_id} in the guild with id {guild_id}"
-        )) {
-        Ok(()) => {
-            if let Ok(role) = api::get_role_id(guild_id).await {
-                if let Err(e) = ctx
-                    .http
-                    .add_member_role(guild_id, user_id, role, None)
-                    .await
-                    .context(concat!(file!(), ":", line!()))
-                {
-                    warn!("Could not add verified role. {e:?}");
-                }
-                return IsVerified {
-                    guild_id,
-                    user_id,
-                    verified: true,
-                };
+    let api = &state(ctx).await.api();
+    let result = match api::is_verified(api, user_id, guild_id).await {
+        Ok(()) => api::get_guild(api, guild_id).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(guild) => {
+            if let Err(e) = add_verified_role(ctx, guild_id, user_id, guild)
+                .await
+                .context(concat!(file!(), ":", line!()))
+            {
+                warn!("Could not add verified role. {e:?}");
+            }
+            IsVerified {
+                guild_id,
+                user_id,
+                verified: true,
+                retry: RetryPolicy::Never,
             }
         }
         Err(e) => {
-            warn!("{e:?}");
+            if !matches!(e, ApiError::NotVerified) {
+                warn!("Could not batch verify user with id {user_id} in the guild with id {guild_id}: {e:?}");
+            }
+            IsVerified {
+                guild_id,
+                user_id,
+                verified: false,
+                retry: e.retry_policy(),
+            }
         }
     }
-    IsVerified {
-        guild_id,
+}
+
+/// Verifies a member, giving them the guild's unverified role if that fails.
+async fn verify_member(ctx: &Context, member: Member) -> IsVerified {
+    let verified = silent_verify(ctx, member.user.id, member.guild_id).await;
+    if !verified.verified {
+        mark_unverified(ctx, &member).await;
+    }
+    verified
+}
+
+/// Gives a member the guild's unverified role, if it has one and they don't already hold it.
+pub async fn mark_unverified(ctx: &Context, member: &Member) {
+    let Ok(guild) = api::get_guild(&state(ctx).await.api(), member.guild_id).await else {
+        return;
+    };
+    if let Some(unverified) = guild
+        .unverified_role_id
+        .filter(|r| !member.roles.contains(r))
+    {
+        if let Err(e) = change_roles(
+            ctx,
+            member.guild_id,
+            RoleChange::Add(member.user.id, unverified),
+        )
+        .await
+        {
+            warn!("Could not add unverified role. {e:?}");
+        }
+    }
+}
+
+/// Adds the verified role, swapping it for the unverified role in a single request when the guild
+/// has one so a member never holds both or neither. Graduates are given the alumni role instead,
+/// if the guild has one, so the next sync doesn't just take the verified role back.
+async fn add_verified_role(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    guild: api::Guild,
+) -> Result<()> {
+    let role = match guild.alumni_role_id {
+        Some(alumni) => match is_graduate(ctx, guild_id, user_id).await {
+            Ok(true) => alumni,
+            Ok(false) => guild.role_id,
+            Err(e) => {
+                warn!("Could not check whether user {user_id} has graduated. {e:?}");
+                guild.role_id
+            }
+        },
+        None => guild.role_id,
+    };
+    let change = match guild.unverified_role_id {
+        Some(unverified) => RoleChange::Swap {
+            user_id,
+            remove: unverified,
+            add: role,
+        },
+        None => RoleChange::Add(user_id, role),
+    };
+    change_roles(ctx, guild_id, change).await?;
+    // Left to run in the background so whoever is waiting to be told they are verified isn't
+    // held up by the membership lookup and another queued role change.
+    if guild.member_role_id.is_some() {
+        let ctx = ctx.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = sync_member_role(&ctx, guild_id, user_id, guild, None).await {
+                    warn!("Could not update member role. {e:?}");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    Ok(())
+}
+
+/// Swaps a graduate's verified role for the guild's alumni role, if it has one.
+pub async fn graduate(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    guild: api::Guild,
+) -> Result<()> {
+    let Some(alumni_role) = guild.alumni_role_id else {
+        return Ok(());
+    };
+    let change = RoleChange::Swap {
         user_id,
-        verified: false,
+        remove: guild.role_id,
+        add: alumni_role,
+    };
+    change_roles(ctx, guild_id, change).await
+}
+
+/// Whether the API says a user has graduated.
+async fn is_graduate(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Result<bool> {
+    let api = state(ctx).await.api();
+    match api::get_verified(&api, user_id, guild_id).await {
+        Ok(verified) => Ok(verified.status == Status::Graduate),
+        Err(ApiError::NotVerified) => Ok(false),
+        Err(e) => Err(e).context(concat!(file!(), ":", line!())),
     }
 }
 
+/// Gives a verified user the guild's member role if they have paid for membership, taking it
+/// away if they haven't. `held` is whether they already have it, if known, to skip needless
+/// changes.
+pub async fn sync_member_role(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    guild: api::Guild,
+    held: Option<bool>,
+) -> Result<()> {
+    let Some(member_role) = guild.member_role_id else {
+        return Ok(());
+    };
+    let api = state(ctx).await.api();
+    let member = match api::is_member(&api, user_id, guild_id).await {
+        Ok(member) => member,
+        Err(ApiError::NotVerified) => false,
+        Err(e) => return Err(e).context(concat!(file!(), ":", line!())),
+    };
+    set_member_role(ctx, guild_id, user_id, member_role, member, held).await
+}
+
+/// Gives a user `member_role` if they have paid for membership or takes it away if they haven't,
+/// for when the API has already been asked.
+pub async fn set_member_role(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    member_role: RoleId,
+    member: bool,
+    held: Option<bool>,
+) -> Result<()> {
+    let change = match (member, held) {
+        (true, Some(true)) | (false, Some(false)) => return Ok(()),
+        (true, _) => RoleChange::Add(user_id, member_role),
+        (false, _) => RoleChange::Remove(user_id, member_role),
+    };
+    change_roles(ctx, guild_id, change).await
+}
+
 async fn create_modal(
     ctx: &Context,
     command: &CommandInteraction,
     partial_guild: &PartialGuild,
-) -> Result<impl Stream<Item = ModalInteraction>> {
+) -> Result<Option<ModalInteraction>> {
     command
         .create_response(
             ctx,
@@ -215,12 +554,13 @@ async fn create_modal(
         .await
         .context(concat!(file!(), ":", line!()))?;
 
-    Ok(ModalInteractionCollector::new(ctx)
-        .guild_id(command.guild_id.unwrap())
-        .author_id(command.user.id)
-        .timeout(Duration::from_secs(60 * 15))
-        .filter(|modal| modal.data.custom_id == "setup-modal")
-        .stream())
+    Ok(wait_for_modal(
+        command.guild_id.unwrap(),
+        command.user.id,
+        "setup-modal",
+        Duration::from_secs(60 * 15),
+    )
+    .await)
 }
 
 async fn get_verified_role(
@@ -232,7 +572,7 @@ async fn get_verified_role(
     let current_user = ctx.cache.current_user().id;
     let bot = guild_id.member(ctx, current_user).await?;
 
-    if bot.permissions.map_or(false, |p| !p.manage_roles()) {
+    if bot.permissions.is_some_and(|p| !p.manage_roles()) {
         command
             .create_response(
                 ctx,
@@ -287,23 +627,124 @@ async fn get_verified_role(
     Ok(role)
 }
 
+/// Gets an optional role from `option`, such as the role members hold until they are verified.
+/// `name` is used in errors, and the role must differ from every role in `taken`.
+async fn get_optional_role(
+    ctx: &Context,
+    command: &CommandInteraction,
+    partial_guild: &PartialGuild,
+    option: &str,
+    name: &str,
+    taken: &[RoleId],
+) -> Result<Option<Role>> {
+    let Some(role_id) = command
+        .data
+        .options
+        .iter()
+        .find(|o| o.name == option)
+        .and_then(|o| o.value.as_role_id())
+    else {
+        return Ok(None);
+    };
+    let role = partial_guild
+        .roles
+        .get(&role_id)
+        .cloned()
+        .ok_or_else(|| anyhow!("Unable to find {name} role {role_id}."))?;
+
+    let current_user = ctx.cache.current_user().id;
+    let bot = partial_guild.id.member(ctx, current_user).await?;
+    let bot_position = bot
+        .roles
+        .iter()
+        .filter_map(|r| partial_guild.roles.get(r).map(|r| r.position))
+        .max();
+
+    if let Some(position) = bot_position {
+        if role.position > position {
+            command.create_response(ctx, CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().content(format!("Unable to use the {name} role, please make sure my role has higher permissions than the {name} role."))))
+                .await.context(concat!(file!(), ":", line!()))?;
+
+            bail!(
+                "{name} role {} ({}) has higher position than bot role.",
+                role.name,
+                role.id
+            )
+        }
+    }
+    if role.id.get() == partial_guild.id.get() || taken.contains(&role.id) {
+        command.create_response(ctx, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(format!("Unable to use the {name} role, it must be different to the other roles and not @everyone."))))
+            .await.context(concat!(file!(), ":", line!()))?;
+        bail!("{name} role is @everyone or another of the bot's roles.")
+    }
+
+    Ok(Some(role))
+}
+
 pub async fn setup(ctx: &Context, command: CommandInteraction) -> Result<()> {
     let partial_guild = command.guild_id.unwrap().to_partial_guild(ctx).await?;
     let verified = get_verified_role(ctx, &command, &partial_guild)
         .await
         .context(concat!(file!(), ":", line!()))
         .context("Tried getting verified role.")?;
+    let unverified = get_optional_role(
+        ctx,
+        &command,
+        &partial_guild,
+        "unverified-role",
+        "unverified",
+        &[verified.id],
+    )
+    .await
+    .context(concat!(file!(), ":", line!()))
+    .context("Tried getting unverified role.")?;
+    let taken = [Some(verified.id), unverified.as_ref().map(|r| r.id)];
+    let member = get_optional_role(
+        ctx,
+        &command,
+        &partial_guild,
+        "member-role",
+        "member",
+        &taken.into_iter().flatten().collect::<Vec<_>>(),
+    )
+    .await
+    .context(concat!(file!(), ":", line!()))
+    .context("Tried getting member role.")?;
+    let taken = [
+        Some(verified.id),
+        unverified.as_ref().map(|r| r.id),
+        member.as_ref().map(|r| r.id),
+    ];
+    let alumni = get_optional_role(
+        ctx,
+        &command,
+        &partial_guild,
+        "alumni-role",
+        "alumni",
+        &taken.into_iter().flatten().collect::<Vec<_>>(),
+    )
+    .await
+    .context(concat!(file!(), ":", line!()))
+    .context("Tried getting alumni role.")?;
 
     let command = create_modal(ctx, &command, &partial_guild)
         .await
         .context(concat!(file!(), ":", line!()))
         .context("creating modal")?
-        .next()
-        .await
         .ok_or_else(|| anyhow!("Did not receive response"))?;
 
+    let api = state(ctx).await.api();
     match join!(
-        modal_response(&command, verified, partial_guild),
+        modal_response(
+            &api,
+            &command,
+            verified,
+            unverified,
+            member,
+            alumni,
+            partial_guild
+        ),
         command.defer(ctx)
     ) {
         (Ok(c), _) => {
@@ -312,7 +753,7 @@ pub async fn setup(ctx: &Context, command: CommandInteraction) -> Result<()> {
                 .await
                 .context(concat!(file!(), ":", line!()))?;
             {
-                let mut cache = api::GET_ROLE_ID.lock().await;
+                let mut cache = api::GET_GUILD.lock().await;
                 cache.cache_remove(&command.guild_id.unwrap());
             }
             Ok(())
@@ -331,8 +772,12 @@ pub async fn setup(ctx: &Context, command: CommandInteraction) -> Result<()> {
 }
 
 async fn modal_response(
+    api: &ApiClient,
     command: &ModalInteraction,
     verified: Role,
+    unverified: Option<Role>,
+    member: Option<Role>,
+    alumni: Option<Role>,
     partial_guild: PartialGuild,
 ) -> Result<&'static str> {
     let (mut name, mut susu, mut invite) = (None, None, None);
@@ -365,24 +810,38 @@ async fn modal_response(
         Some(Ok(l)) => Some(l),
         None => None,
     };
+    ensure!(
+        member.is_none() || susu_link.is_some(),
+        "A SUSU link is needed to check who has paid for membership, please add one or leave out the member role."
+    );
     let invite_link = Url::parse(&invite.ok_or_else(|| anyhow!("invite was not sent."))?)
         .context("Unable to parse invite link, please make sure it is a url.")?;
 
-    let resp = register_guild(RegisterParams {
-        guild_id: partial_guild.id,
-        name,
-        icon: partial_guild.icon.map(|i| i.to_string()),
-        created_at: partial_guild.id.created_at(),
-        owner_id: partial_guild.owner_id,
-        susu_link,
-        invite_link,
-        role_id: verified.id,
-        role_name: verified.name,
-        role_colour: verified.colour,
-    })
+    let resp = register_guild(
+        api,
+        RegisterParams {
+            guild_id: partial_guild.id,
+            name,
+            icon: partial_guild.icon.map(|i| i.to_string()),
+            created_at: partial_guild.id.created_at(),
+            owner_id: partial_guild.owner_id,
+            susu_link,
+            invite_link,
+            role_id: verified.id,
+            role_name: verified.name,
+            role_colour: verified.colour,
+            unverified_role_id: unverified.map(|r| r.id),
+            member_role_id: member.map(|r| r.id),
+            alumni_role_id: alumni.map(|r| r.id),
+        },
+    )
     .await
-    .context(concat!(file!(), ":", line!()))
-    .context("Could not register guild, are you sure you haven't already registered?")?;
+    .map_err(|e| {
+        let message = e.admin_message();
+        anyhow::Error::new(e)
+            .context(concat!(file!(), ":", line!()))
+            .context(message)
+    })?;
 
     // bail if registered is not true
     ensure!(resp.registered, "Error guild info was not saved to the db");
@@ -1,47 +1,97 @@
+use anyhow::Context as ContextTrait;
 use cached::proc_macro::cached;
+use cached::Cached;
 
-use once_cell::sync::Lazy;
 use reqwest::header::HeaderMap;
 use reqwest::header::HeaderValue;
-use reqwest::{Client, ClientBuilder, Url};
+use reqwest::{Client, ClientBuilder, Response, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 
-use anyhow::Result;
-use anyhow::{anyhow, ensure};
 use serenity::model::prelude::{GuildId, RoleId, UserId};
 use serenity::model::Timestamp;
 
-use log::warn;
 use serenity::all::Colour;
-use std::env;
+use std::collections::HashSet;
 use std::time::{Duration, Instant};
 use thiserror::Error;
+use tracing::{instrument, warn};
 
-#[derive(Error, Debug)]
-enum ParamError {
+use crate::config::Config;
+
+#[derive(Error, Debug, Clone)]
+pub enum ApiError {
+    #[error("User does not exist or is not verified.")]
+    NotVerified,
+    #[error("Guild has not been registered.")]
+    GuildNotRegistered,
     #[error("Incorrect Authorization header.")]
-    IncorrectAuth,
-    #[error("Incorrect parameters: {0:?}.")]
-    InvalidParams(reqwest::Result<String>),
-}
-
-static CLIENT: Lazy<Client> = Lazy::new(|| {
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        "Authorization",
-        HeaderValue::from_str(
-            &env::var("API_KEY").expect("API_KEY environment var has not been set"),
-        )
-        .unwrap(),
-    );
-    ClientBuilder::new()
-        .default_headers(headers)
-        .build()
-        .unwrap()
-});
+    Unauthorized,
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Guild has already been registered.")]
+    AlreadyRegistered,
+    #[error("Verify service is unavailable: {0}")]
+    Unavailable(String),
+    #[error("Rate limited by the verify service, retry after {0:?}.")]
+    RateLimited(Option<Duration>),
+    /// The response didn't have the shape the bot expects, such as after an API change.
+    #[error("Could not decode the verify service's response: {0}")]
+    InvalidResponse(String),
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            ApiError::InvalidResponse(e.to_string())
+        } else {
+            ApiError::Unavailable(e.to_string())
+        }
+    }
+}
+
+/// Maps the status codes every endpoint shares to an error.
+async fn error_for(resp: Response) -> ApiError {
+    match resp.status() {
+        StatusCode::UNAUTHORIZED => ApiError::Unauthorized,
+        StatusCode::BAD_REQUEST => ApiError::BadRequest(resp.text().await.unwrap_or_default()),
+        StatusCode::TOO_MANY_REQUESTS => ApiError::RateLimited(
+            resp.headers()
+                .get("Retry-After")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse().ok())
+                .map(Duration::from_secs),
+        ),
+        status => ApiError::Unavailable(format!("Unexpected status {status}")),
+    }
+}
+
+/// Client for the verify API.
+#[derive(Clone, Debug)]
+pub struct ApiClient {
+    client: Client,
+    base_url: String,
+}
+
+impl ApiClient {
+    pub fn new(config: &Config) -> anyhow::Result<ApiClient> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            HeaderValue::from_str(&config.api_key).context("API_KEY is not a valid header")?,
+        );
+        Ok(ApiClient {
+            client: ClientBuilder::new().default_headers(headers).build()?,
+            base_url: config.api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+}
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
-struct Verified {
+pub struct Verified {
     pub verified: bool,
     #[serde(rename = "roleId")]
     pub role_id: RoleId,
@@ -49,6 +99,21 @@ struct Verified {
     pub soton_linked_date: Timestamp,
     #[serde(rename = "discordLinkedDate")]
     pub discord_linked_date: Timestamp,
+    /// Older versions of the API don't send a status, everyone they know is a student.
+    #[serde(default)]
+    pub status: Status,
+}
+
+/// Where a verified user is in their studies.
+#[derive(Copy, Clone, Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Student,
+    Graduate,
+    /// A status added to the API since this version of the bot.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
@@ -59,15 +124,43 @@ struct VerifiedParams {
     pub guild_id: GuildId,
 }
 
-#[cached(key = "UserId", result = true, convert = r##"{user_id}"##)]
-pub async fn is_verified(user_id: UserId, guild_id: GuildId) -> Result<()> {
+// Keyed by guild too, as batch lookups only say whether users are verified for that guild.
+#[cached(
+    key = "(UserId, GuildId)",
+    result = true,
+    convert = r##"{(user_id, guild_id)}"##
+)]
+#[instrument(skip_all, fields(guild = %guild_id, user = %user_id))]
+pub async fn is_verified(
+    api: &ApiClient,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<(), ApiError> {
+    let resp = get_verified(api, user_id, guild_id).await?;
+    if !resp.verified {
+        return Err(ApiError::NotVerified);
+    }
+    {
+        let mut cache = GET_GUILD.lock().await;
+        if let Some(guild) = cache.cache_get_mut(&guild_id) {
+            guild.role_id = resp.role_id;
+        }
+    }
+    Ok(())
+}
+
+/// Gets a user's verification record, this is never cached.
+#[instrument(skip_all, fields(guild = %guild_id, user = %user_id))]
+pub async fn get_verified(
+    api: &ApiClient,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<Verified, ApiError> {
     let elapsed = Instant::now();
     let params = VerifiedParams { user_id, guild_id };
-    let resp = CLIENT
-        .get(
-            &*(env::var("API_URL").expect("API_URL environment var has not been set")
-                + "/api/v1/verified"),
-        )
+    let resp = api
+        .client
+        .get(api.url("/api/v1/verified"))
         .json(&params)
         .send()
         .await?;
@@ -77,28 +170,182 @@ pub async fn is_verified(user_id: UserId, guild_id: GuildId) -> Result<()> {
     }
 
     match resp.status().into() {
-        200 => {
-            let resp = resp.json::<Verified>().await?;
-            ensure!(resp.verified, "User ({params:?}) is not verified.");
-            {
-                let mut cache = GET_ROLE_ID.lock().await;
-                cache.cache_set(guild_id, resp.role_id);
+        200 => Ok(resp.json::<Verified>().await?),
+        404 => Err(ApiError::NotVerified),
+        _ => Err(error_for(resp).await),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Membership {
+    pub member: bool,
+}
+
+/// Checks whether a verified user has paid for membership of the society behind the guild's SUSU
+/// link, this is never cached as memberships expire.
+#[instrument(skip_all, fields(guild = %guild_id, user = %user_id))]
+pub async fn is_member(
+    api: &ApiClient,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<bool, ApiError> {
+    let elapsed = Instant::now();
+    let params = VerifiedParams { user_id, guild_id };
+    let resp = api
+        .client
+        .get(api.url("/api/v1/membership"))
+        .json(&params)
+        .send()
+        .await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!("Took {elapsed:?} to check if user is a member.");
+    }
+
+    match resp.status().into() {
+        200 => Ok(resp.json::<Membership>().await?.member),
+        404 => Err(ApiError::NotVerified),
+        _ => Err(error_for(resp).await),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct BatchParams<'a> {
+    #[serde(rename = "userIds")]
+    pub user_ids: &'a [UserId],
+    #[serde(rename = "guildId")]
+    pub guild_id: GuildId,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchVerified {
+    pub verified: Vec<UserId>,
+    /// Which of the verified users have graduated, left out by older versions of the API.
+    #[serde(default)]
+    pub graduates: Option<Vec<UserId>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchMembership {
+    pub members: Vec<UserId>,
+}
+
+/// Looks up a batch of users in a single request, returning `None` if the API doesn't support
+/// batch lookups. Verified users are added to the `is_verified` cache.
+async fn batch_lookup(
+    api: &ApiClient,
+    user_ids: &[UserId],
+    guild_id: GuildId,
+) -> Result<Option<BatchVerified>, ApiError> {
+    let elapsed = Instant::now();
+    let resp = api
+        .client
+        .post(api.url("/api/v1/verified/batch"))
+        .json(&BatchParams { user_ids, guild_id })
+        .send()
+        .await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!(
+            "Took {elapsed:?} to check if {} users are verified.",
+            user_ids.len()
+        );
+    }
+
+    match resp.status() {
+        StatusCode::OK => {
+            let batch = resp.json::<BatchVerified>().await?;
+            let mut cache = IS_VERIFIED.lock().await;
+            for user_id in &batch.verified {
+                cache.cache_set((*user_id, guild_id), ());
             }
-            Ok(())
+            Ok(Some(batch))
+        }
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            Ok(None)
         }
-        404 => Err(anyhow!(
-            "User ({params:?}) does not exist or is not verified."
+        _ => Err(error_for(resp).await),
+    }
+}
+
+/// Checks which of `user_ids` are verified in a single request, returning `None` if the API
+/// doesn't support batch lookups. Verified users are added to the `is_verified` cache.
+#[instrument(skip_all, fields(guild = %guild_id, users = user_ids.len()))]
+pub async fn batch_verified(
+    api: &ApiClient,
+    user_ids: &[UserId],
+    guild_id: GuildId,
+) -> Result<Option<HashSet<UserId>>, ApiError> {
+    let batch = batch_lookup(api, user_ids, guild_id).await?;
+    Ok(batch.map(|b| b.verified.into_iter().collect()))
+}
+
+/// Checks which of `user_ids` have graduated in a single request, returning `None` if the API
+/// doesn't support batch lookups or doesn't report graduates in them.
+#[instrument(skip_all, fields(guild = %guild_id, users = user_ids.len()))]
+pub async fn batch_graduates(
+    api: &ApiClient,
+    user_ids: &[UserId],
+    guild_id: GuildId,
+) -> Result<Option<HashSet<UserId>>, ApiError> {
+    let batch = batch_lookup(api, user_ids, guild_id).await?;
+    Ok(batch
+        .and_then(|b| b.graduates)
+        .map(|g| g.into_iter().collect()))
+}
+
+/// Checks which of `user_ids` have paid for membership in a single request, returning `None` if
+/// the API doesn't support batch lookups. Like `is_member` this is never cached.
+#[instrument(skip_all, fields(guild = %guild_id, users = user_ids.len()))]
+pub async fn batch_members(
+    api: &ApiClient,
+    user_ids: &[UserId],
+    guild_id: GuildId,
+) -> Result<Option<HashSet<UserId>>, ApiError> {
+    let elapsed = Instant::now();
+    let resp = api
+        .client
+        .post(api.url("/api/v1/membership/batch"))
+        .json(&BatchParams { user_ids, guild_id })
+        .send()
+        .await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!(
+            "Took {elapsed:?} to check if {} users are members.",
+            user_ids.len()
+        );
+    }
+
+    match resp.status() {
+        StatusCode::OK => Ok(Some(
+            resp.json::<BatchMembership>()
+                .await?
+                .members
+                .into_iter()
+                .collect(),
         )),
-        401 => Err(ParamError::IncorrectAuth.into()),
-        400 => Err(ParamError::InvalidParams(resp.text().await).into()),
-        _ => Err(anyhow!("Unknown error: {resp:?}")),
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            Ok(None)
+        }
+        _ => Err(error_for(resp).await),
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct Guild {
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub struct Guild {
     #[serde(rename = "roleId")]
     pub role_id: RoleId,
+    /// Role held by members until they are verified, if the guild uses one.
+    #[serde(rename = "unverifiedRoleId", default)]
+    pub unverified_role_id: Option<RoleId>,
+    /// Role held by verified members who have paid for membership of the society behind the
+    /// guild's SUSU link, if the guild uses one.
+    #[serde(rename = "memberRoleId", default)]
+    pub member_role_id: Option<RoleId>,
+    /// Role the verified role is swapped for once a member graduates, if the guild uses one.
+    #[serde(rename = "alumniRoleId", default)]
+    pub alumni_role_id: Option<RoleId>,
     pub approved: bool,
 }
 
@@ -108,31 +355,25 @@ struct GuildParams {
     pub guild_id: GuildId,
 }
 
-#[cached(result = true)]
-pub async fn get_role_id(guild_id: GuildId) -> Result<RoleId> {
+#[cached(key = "GuildId", result = true, convert = r##"{guild_id}"##)]
+#[instrument(skip_all, fields(guild = %guild_id))]
+pub async fn get_guild(api: &ApiClient, guild_id: GuildId) -> Result<Guild, ApiError> {
     let elapsed = Instant::now();
-    let resp = CLIENT
-        .get(
-            env::var("API_URL").expect("API_URL environment var has not been set")
-                + &*format!("/api/v1/guild/{guild_id}"),
-        )
+    let resp = api
+        .client
+        .get(api.url(&format!("/api/v1/guild/{guild_id}")))
         .json(&GuildParams { guild_id })
         .send()
         .await?;
 
     let elapsed = elapsed.elapsed();
     if elapsed > Duration::from_millis(400) {
-        warn!("Took {elapsed:?} to get role id.");
+        warn!("Took {elapsed:?} to get guild.");
     }
     match resp.status().into() {
-        200 => {
-            let resp = resp.json::<Guild>().await?;
-            Ok(resp.role_id)
-        }
-        404 => Err(anyhow!("Guild with id of {guild_id} does not exist.")),
-        401 => Err(ParamError::IncorrectAuth.into()),
-        400 => Err(ParamError::InvalidParams(resp.text().await).into()),
-        _ => Err(anyhow!("Unknown error: {resp:?}")),
+        200 => Ok(resp.json::<Guild>().await?),
+        404 => Err(ApiError::GuildNotRegistered),
+        _ => Err(error_for(resp).await),
     }
 }
 
@@ -156,6 +397,12 @@ pub struct RegisterParams {
     pub role_name: String,
     #[serde(rename = "roleColour")]
     pub role_colour: Colour,
+    #[serde(rename = "unverifiedRoleId")]
+    pub unverified_role_id: Option<RoleId>,
+    #[serde(rename = "memberRoleId")]
+    pub member_role_id: Option<RoleId>,
+    #[serde(rename = "alumniRoleId")]
+    pub alumni_role_id: Option<RoleId>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -164,13 +411,12 @@ pub struct Register {
     pub approved: bool,
 }
 
-pub async fn register_guild(info: RegisterParams) -> Result<Register> {
+#[instrument(skip_all, fields(guild = %info.guild_id))]
+pub async fn register_guild(api: &ApiClient, info: RegisterParams) -> Result<Register, ApiError> {
     let elapsed = Instant::now();
-    let resp = CLIENT
-        .post(
-            env::var("API_URL").expect("API_URL environment var has not been set")
-                + "/api/v1/guild/register",
-        )
+    let resp = api
+        .client
+        .post(api.url("/api/v1/guild/register"))
         .json(&info)
         .send()
         .await?;
@@ -181,12 +427,80 @@ pub async fn register_guild(info: RegisterParams) -> Result<Register> {
 
     match resp.status().into() {
         200 => Ok(resp.json::<Register>().await?),
-        409 => Err(anyhow!(
-            "Guild with id of {} has already been registered.",
-            info.guild_id
-        )),
-        401 => Err(ParamError::IncorrectAuth.into()),
-        400 => Err(ParamError::InvalidParams(resp.text().await).into()),
-        _ => Err(anyhow!("Unknown error: {resp:?}")),
+        409 => Err(ApiError::AlreadyRegistered),
+        _ => Err(error_for(resp).await),
+    }
+}
+
+/// Lets the API know the bot is no longer in a guild.
+#[instrument(skip_all, fields(guild = %guild_id))]
+pub async fn leave_guild(api: &ApiClient, guild_id: GuildId) -> Result<(), ApiError> {
+    let elapsed = Instant::now();
+    let resp = api
+        .client
+        .post(api.url(&format!("/api/v1/guild/{guild_id}/leave")))
+        .json(&GuildParams { guild_id })
+        .send()
+        .await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!("Took {elapsed:?} to leave guild.");
+    }
+
+    match resp.status().into() {
+        200 => Ok(()),
+        404 => Err(ApiError::GuildNotRegistered),
+        _ => Err(error_for(resp).await),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Registration {
+    #[serde(rename = "guildId")]
+    pub guild_id: GuildId,
+    pub name: String,
+    pub approved: bool,
+}
+
+/// Lists every guild that has been registered with the API.
+#[instrument(skip_all)]
+pub async fn list_guilds(api: &ApiClient) -> Result<Vec<Registration>, ApiError> {
+    let elapsed = Instant::now();
+    let resp = api.client.get(api.url("/api/v1/guilds")).send().await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!("Took {elapsed:?} to list guilds.");
+    }
+
+    match resp.status().into() {
+        200 => Ok(resp.json::<Vec<Registration>>().await?),
+        _ => Err(error_for(resp).await),
+    }
+}
+
+/// Approves or rejects a guild's registration.
+#[instrument(skip_all, fields(guild = %guild_id, approve))]
+pub async fn review_guild(
+    api: &ApiClient,
+    guild_id: GuildId,
+    approve: bool,
+) -> Result<(), ApiError> {
+    let elapsed = Instant::now();
+    let action = if approve { "approve" } else { "reject" };
+    let resp = api
+        .client
+        .post(api.url(&format!("/api/v1/guild/{guild_id}/{action}")))
+        .json(&GuildParams { guild_id })
+        .send()
+        .await?;
+    let elapsed = elapsed.elapsed();
+    if elapsed > Duration::from_millis(400) {
+        warn!("Took {elapsed:?} to {action} guild.");
+    }
+
+    match resp.status().into() {
+        200 => Ok(()),
+        404 => Err(ApiError::GuildNotRegistered),
+        _ => Err(error_for(resp).await),
     }
 }
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use anyhow::Context as ContextTrait;
+use anyhow::Result;
+use serenity::all::{
+    ChannelType, CommandInteraction, CreateAttachment, EditInteractionResponse, GuildChannel,
+    Mentionable, PermissionOverwriteType, Permissions, Role,
+};
+use serenity::client::Context;
+use serenity::model::prelude::{GuildId, RoleId};
+
+use crate::commands::api;
+use crate::state::state;
+
+/// Longest report we'll send as a message before falling back to an attachment.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+/// Works out the permissions a member holding only `held` (plus @everyone) would have in a
+/// channel, ignoring any member specific overwrites.
+fn effective_permissions(
+    guild_id: GuildId,
+    roles: &HashMap<RoleId, Role>,
+    held: &[RoleId],
+    channel: &GuildChannel,
+) -> Permissions {
+    let everyone = RoleId::new(guild_id.get());
+    let base = held
+        .iter()
+        .chain([&everyone])
+        .filter_map(|r| roles.get(r))
+        .fold(Permissions::empty(), |p, r| p | r.permissions);
+    if base.administrator() {
+        return Permissions::all();
+    }
+
+    let mut permissions = base;
+    let (mut allow, mut deny) = (Permissions::empty(), Permissions::empty());
+    for overwrite in &channel.permission_overwrites {
+        match overwrite.kind {
+            PermissionOverwriteType::Role(r) if r == everyone => {
+                permissions = (permissions - overwrite.deny) | overwrite.allow;
+            }
+            PermissionOverwriteType::Role(r) if held.contains(&r) => {
+                allow |= overwrite.allow;
+                deny |= overwrite.deny;
+            }
+            _ => {}
+        }
+    }
+    permissions = (permissions - deny) | allow;
+
+    if !permissions.view_channel() {
+        return Permissions::empty();
+    }
+    permissions
+}
+
+/// Role-me style channels are the most likely to leak role gated channels to unverified members.
+fn is_role_channel(channel: &GuildChannel) -> bool {
+    channel.name.contains("role")
+}
+
+/// Lists every channel unverified members can see or send messages in.
+pub async fn audit(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+
+    let guild = match api::get_guild(&state(ctx).await.api(), guild_id).await {
+        Ok(guild) => guild,
+        Err(e) => {
+            command
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new().content(e.admin_message()),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+    let roles = guild_id
+        .roles(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let mut channels = guild_id
+        .channels(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?
+        .into_values()
+        .filter(|c| c.kind != ChannelType::Category)
+        .collect::<Vec<_>>();
+    channels.sort_by_key(|c| (c.parent_id, c.position));
+
+    let unverified_roles = guild.unverified_role_id.into_iter().collect::<Vec<_>>();
+    let verified_roles = [guild.role_id];
+
+    let mut leaks = vec![];
+    let mut hidden = vec![];
+    for channel in &channels {
+        let unverified = effective_permissions(guild_id, &roles, &unverified_roles, channel);
+        let access = match (unverified.view_channel(), unverified.send_messages()) {
+            (true, true) => "view and send",
+            (true, false) => "view",
+            _ => "",
+        };
+        if !access.is_empty() {
+            leaks.push(if is_role_channel(channel) {
+                format!(
+                    "⚠️ {} ({access}), role assignment channels should be hidden from unverified members",
+                    channel.mention()
+                )
+            } else {
+                format!("- {} ({access})", channel.mention())
+            });
+        }
+
+        if !effective_permissions(guild_id, &roles, &verified_roles, channel).view_channel() {
+            hidden.push(format!("- {}", channel.mention()));
+        }
+    }
+
+    let holding = match guild.unverified_role_id {
+        Some(role) => format!("@everyone and {}", role.mention()),
+        None => "only @everyone".to_string(),
+    };
+    let mut report = vec![format!(
+        "**Channels unverified members (holding {holding}) can access:**"
+    )];
+    if leaks.is_empty() {
+        report.push("None, unverified members can't see anything.".to_string());
+    } else {
+        report.extend(leaks);
+    }
+    report.push(format!(
+        "\n**Channels verified members (holding {}) can't see:**",
+        guild.role_id.mention()
+    ));
+    if hidden.is_empty() {
+        report.push("None.".to_string());
+    } else {
+        report.extend(hidden);
+    }
+    let report = report.join("\n");
+
+    let response = if report.len() > MAX_MESSAGE_LEN {
+        EditInteractionResponse::new()
+            .content("The report was too long for a message so it is attached.")
+            .new_attachment(CreateAttachment::bytes(report.into_bytes(), "audit.txt"))
+    } else {
+        EditInteractionResponse::new().content(report)
+    };
+    command
+        .edit_response(ctx, response)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use anyhow::Context as ContextTrait;
+use cached::Cached;
+use once_cell::sync::Lazy;
+use serenity::all::{Mentionable, Role};
+use serenity::client::Context;
+use serenity::model::prelude::{GuildId, RoleId};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::commands::api;
+use crate::commands::api::ApiError;
+use crate::commands::roles::forget_queue;
+use crate::history;
+use crate::scheduler::Task;
+use crate::state::{state, AppState};
+
+/// Roles we have already warned a guild about, so reordering roles doesn't spam the admins.
+static ALERTED: Lazy<Mutex<HashSet<(GuildId, RoleId)>>> = Lazy::new(Default::default);
+
+/// Drops cached info for a guild and stops any queued verifications and role changes in it.
+async fn forget_guild(state: &AppState, guild_id: GuildId) {
+    {
+        let mut cache = api::GET_GUILD.lock().await;
+        cache.cache_remove(&guild_id);
+    }
+    state.tasks.send(Task::CancelGuild(guild_id)).ok();
+    forget_queue(guild_id);
+}
+
+/// Cleans up after the bot has been removed from a guild.
+pub async fn guild_left(ctx: &Context, guild_id: GuildId) {
+    let state = state(ctx).await;
+    forget_guild(&state, guild_id).await;
+    ALERTED.lock().await.retain(|(g, _)| *g != guild_id);
+    history::forget(guild_id).await;
+    match api::leave_guild(&state.api(), guild_id).await {
+        Ok(()) | Err(ApiError::GuildNotRegistered) => {}
+        Err(e) => warn!("Could not tell the API we left guild {guild_id}: {e:?}"),
+    }
+}
+
+/// Warns the admins if a role the bot hands out has been deleted.
+pub async fn role_deleted(ctx: &Context, guild_id: GuildId, role_id: RoleId) {
+    let state = state(ctx).await;
+    let Ok(guild) = api::get_guild(&state.api(), guild_id).await else {
+        return;
+    };
+    let optional = if Some(role_id) == guild.member_role_id {
+        Some(("member", "mark paid members"))
+    } else if Some(role_id) == guild.alumni_role_id {
+        Some(("alumni", "move graduates to it"))
+    } else {
+        None
+    };
+    if let Some((name, purpose)) = optional {
+        // Verification still works, but the next sync should see the guild's new settings.
+        api::GET_GUILD.lock().await.cache_remove(&guild_id);
+        alert_admins(
+            ctx,
+            guild_id,
+            &format!("The {name} role has been deleted so I can no longer {purpose}, please run /setup again with a new {name} role."),
+        )
+        .await;
+        return;
+    }
+    let (name, purpose) = if role_id == guild.role_id {
+        ("verified", "verify anyone")
+    } else if Some(role_id) == guild.unverified_role_id {
+        (
+            "unverified",
+            "mark new members as unverified or swap it for the verified role",
+        )
+    } else {
+        return;
+    };
+    forget_guild(&state, guild_id).await;
+    alert_admins(
+        ctx,
+        guild_id,
+        &format!("The {name} role has been deleted so I can no longer {purpose}, please run /setup again with a new {name} role."),
+    )
+    .await;
+}
+
+/// Warns the admins if a role update has left the bot unable to hand out one of its roles.
+pub async fn role_updated(ctx: &Context, role: &Role) {
+    let guild_id = role.guild_id;
+    let Ok(guild) = api::get_guild(&state(ctx).await.api(), guild_id).await else {
+        return;
+    };
+    let bot_id = ctx.cache.current_user().id;
+    let Ok(bot) = guild_id.member(ctx, bot_id).await else {
+        return;
+    };
+    if role.id != guild.role_id
+        && Some(role.id) != guild.unverified_role_id
+        && Some(role.id) != guild.member_role_id
+        && Some(role.id) != guild.alumni_role_id
+        && !bot.roles.contains(&role.id)
+    {
+        return;
+    }
+    let roles = match guild_id
+        .roles(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        Ok(roles) => roles,
+        Err(e) => {
+            warn!("Could not check role hierarchy. {e:?}");
+            return;
+        }
+    };
+    let bot_position = bot
+        .roles
+        .iter()
+        .filter_map(|r| roles.get(r).map(|r| r.position))
+        .max()
+        .unwrap_or(0);
+
+    let watched = [
+        ("verified", Some(guild.role_id)),
+        ("unverified", guild.unverified_role_id),
+        ("member", guild.member_role_id),
+        ("alumni", guild.alumni_role_id),
+    ];
+    for (name, role) in watched
+        .into_iter()
+        .filter_map(|(name, id)| id.and_then(|id| roles.get(&id)).map(|r| (name, r)))
+    {
+        let problem = if role.managed {
+            Some(format!("The {name} role {} is now managed by an integration so I can't give it out, please run /setup again with a different role.", role.mention()))
+        } else if role.position >= bot_position {
+            Some(format!("The {name} role {} is now above my highest role so I can't give it out, please move my role above it.", role.mention()))
+        } else {
+            None
+        };
+
+        let key = (guild_id, role.id);
+        match problem {
+            Some(problem) => {
+                if ALERTED.lock().await.insert(key) {
+                    alert_admins(ctx, guild_id, &problem).await;
+                }
+            }
+            None => {
+                ALERTED.lock().await.remove(&key);
+            }
+        }
+    }
+}
+
+/// Sends a message to the guild's system channel, falling back to DMing the owner.
+pub async fn alert_admins(ctx: &Context, guild_id: GuildId, content: &str) {
+    let guild = match guild_id
+        .to_partial_guild(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        Ok(guild) => guild,
+        Err(e) => {
+            warn!("Could not alert the admins of guild {guild_id}: {content} {e:?}");
+            return;
+        }
+    };
+    if let Some(channel) = guild.system_channel_id {
+        if channel.say(ctx, content).await.is_ok() {
+            return;
+        }
+    }
+    let dm =
+        match guild
+            .owner_id
+            .create_dm_channel(ctx)
+            .await
+            .context(concat!(file!(), ":", line!()))
+        {
+            Ok(dm) => dm,
+            Err(e) => {
+                warn!("Could not alert the owner of guild {guild_id}: {content} {e:?}");
+                return;
+            }
+        };
+    if let Err(e) = dm
+        .say(ctx, format!("**{}**: {content}", guild.name))
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        warn!("Could not alert the owner of guild {guild_id}: {content} {e:?}");
+    }
+}
@@ -0,0 +1,47 @@
+//! Hands button presses to the command waiting for them. Like modal submissions they can arrive
+//! over the gateway or the interactions endpoint, so serenity's collectors can't be used.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use serenity::all::ComponentInteraction;
+use serenity::model::prelude::MessageId;
+use tokio::sync::oneshot;
+
+struct Waiter {
+    message_id: MessageId,
+    press: oneshot::Sender<ComponentInteraction>,
+}
+
+static WAITERS: Lazy<Mutex<Vec<Waiter>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Waits for a component on the message with `message_id` to be used.
+pub async fn wait_for_component(
+    message_id: MessageId,
+    timeout: Duration,
+) -> Option<ComponentInteraction> {
+    let (press, recv) = oneshot::channel();
+    {
+        let mut waiters = WAITERS.lock().unwrap();
+        // Drop anyone who stopped waiting so the list doesn't grow forever.
+        waiters.retain(|w| !w.press.is_closed());
+        waiters.push(Waiter { message_id, press });
+    }
+    tokio::time::timeout(timeout, recv).await.ok()?.ok()
+}
+
+/// Passes a component interaction to whoever is waiting for it, returning it if nobody is.
+pub fn deliver_component(press: ComponentInteraction) -> Option<ComponentInteraction> {
+    let waiter = {
+        let mut waiters = WAITERS.lock().unwrap();
+        let position = waiters
+            .iter()
+            .position(|w| w.message_id == press.message.id && !w.press.is_closed());
+        position.map(|i| waiters.remove(i))
+    };
+    match waiter {
+        Some(waiter) => waiter.press.send(press).err(),
+        None => Some(press),
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use anyhow::{anyhow, Context as ContextTrait};
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CreateActionRow, CreateButton, CreateInteractionResponse,
+    EditInteractionResponse, Mentionable, ResolvedTarget, User,
+};
+use serenity::client::Context;
+use tracing::warn;
+
+use crate::commands::api::ApiError;
+use crate::commands::components::wait_for_component;
+use crate::commands::{add_verified_role, api};
+use crate::history::{self, Method};
+use crate::state::state;
+
+/// Target of a user context-menu command along with whether they hold the verified role.
+struct Target {
+    user: User,
+    has_role: bool,
+    guild: api::Guild,
+}
+
+/// Looks up the target of a user context-menu command, responding to the moderator if the server
+/// can't be looked up.
+async fn get_target(ctx: &Context, command: &CommandInteraction) -> Result<Target> {
+    let guild_id = command.guild_id.unwrap();
+    let Some(ResolvedTarget::User(user, member)) = command.data.target() else {
+        return Err(anyhow!("Context menu command was not used on a user."));
+    };
+    match api::get_guild(&state(ctx).await.api(), guild_id).await {
+        Ok(guild) => Ok(Target {
+            user: user.clone(),
+            has_role: member.is_some_and(|m| m.roles.contains(&guild.role_id)),
+            guild,
+        }),
+        Err(e) => {
+            respond(ctx, command, e.admin_message()).await?;
+            Err(e).context(concat!(file!(), ":", line!()))
+        }
+    }
+}
+
+/// Replaces the deferred response with `content`.
+async fn respond(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<()> {
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
+
+/// Tells a moderator whether a member is verified, offering to give them the verified role if
+/// they are verified but don't have it.
+pub async fn check_verification(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let target = get_target(ctx, &command).await?;
+    let mention = target.user.mention();
+    let state = state(ctx).await;
+    match api::is_verified(&state.api(), target.user.id, guild_id).await {
+        Ok(()) => {}
+        Err(ApiError::NotVerified) => {
+            return respond(ctx, &command, format!("{mention} is not verified.")).await;
+        }
+        Err(e) => {
+            respond(ctx, &command, e.admin_message()).await?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    }
+    if target.has_role {
+        return respond(
+            ctx,
+            &command,
+            format!("{mention} is verified and has the verified role."),
+        )
+        .await;
+    }
+
+    let message = command
+        .edit_response(
+            ctx,
+            EditInteractionResponse::new()
+                .content(format!(
+                    "{mention} is verified but doesn't have the verified role."
+                ))
+                .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                    "assign-verified",
+                )
+                .label("Give verified role")
+                .style(ButtonStyle::Primary)])]),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let Some(press) = wait_for_component(message.id, Duration::from_secs(60 * 5)).await else {
+        return Ok(());
+    };
+    press
+        .create_response(ctx, CreateInteractionResponse::Acknowledge)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+
+    let content = match add_verified_role(ctx, guild_id, target.user.id, target.guild)
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        Ok(()) => {
+            history::record_verified(guild_id, &[target.user.id], Method::Moderator).await;
+            format!("Gave {mention} the verified role.")
+        }
+        Err(e) => {
+            warn!("Could not add verified role. {e:?}");
+            "I was unable to add the verified role, please make sure my role has higher permissions than the verified role.".to_string()
+        }
+    };
+    press
+        .edit_response(
+            ctx,
+            EditInteractionResponse::new()
+                .content(content)
+                .components(vec![]),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
+
+/// Verifies another member on a moderator's behalf.
+pub async fn verify_user(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    // Adding the role can wait on the role queue for longer than Discord allows before the first
+    // response.
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let target = get_target(ctx, &command).await?;
+    let mention = target.user.mention();
+    let state = state(ctx).await;
+    match api::is_verified(&state.api(), target.user.id, guild_id).await {
+        Ok(()) => {}
+        Err(ApiError::NotVerified) => {
+            return respond(
+                ctx,
+                &command,
+                format!(
+                    "{mention} is not verified, they will need to go to {} first.",
+                    state.config().display_url
+                ),
+            )
+            .await;
+        }
+        Err(e) => {
+            respond(ctx, &command, e.admin_message()).await?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    }
+    if target.has_role {
+        return respond(ctx, &command, format!("{mention} is already verified.")).await;
+    }
+
+    match add_verified_role(ctx, guild_id, target.user.id, target.guild)
+        .await
+        .context(concat!(file!(), ":", line!()))
+    {
+        Ok(()) => {
+            history::record_verified(guild_id, &[target.user.id], Method::Moderator).await;
+            respond(ctx, &command, format!("{mention} has now been verified!")).await
+        }
+        Err(e) => {
+            respond(ctx, &command, "I was unable to add the verified role, please make sure my role has higher permissions than the verified role.").await?;
+            Err(e).context("Could not add verified role.")
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context as ContextTrait;
+use anyhow::Result;
+use cached::Cached;
+use serenity::all::{
+    Command, CommandInteraction, EditInteractionResponse, Mentionable, Permissions,
+};
+use serenity::client::Context;
+use serenity::model::guild::Role;
+use serenity::model::prelude::{GuildId, RoleId};
+
+use crate::commands::api;
+use crate::create_commands;
+use crate::state::state;
+
+/// The outcome of a single diagnostic check.
+struct Check {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Re-checks everything the bot needs to be able to verify members and reports how to fix
+/// anything that's broken.
+pub async fn diagnose(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    {
+        let mut cache = api::GET_GUILD.lock().await;
+        cache.cache_remove(&guild_id);
+    }
+
+    let roles = guild_id
+        .roles(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let bot_id = ctx.cache.current_user().id;
+    let bot = guild_id
+        .member(ctx, bot_id)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let bot_position = bot
+        .roles
+        .iter()
+        .filter_map(|r| roles.get(r).map(|r| r.position))
+        .max()
+        .unwrap_or(0);
+    let permissions = bot
+        .roles
+        .iter()
+        .chain([&RoleId::new(guild_id.get())])
+        .filter_map(|r| roles.get(r))
+        .fold(Permissions::empty(), |p, r| p | r.permissions);
+
+    let mut checks = vec![
+        if permissions.administrator() || permissions.manage_roles() {
+            Check::pass("Permissions", "I have the Manage Roles permission.")
+        } else {
+            Check::fail("Permissions", "I don't have the Manage Roles permission. Give my role Manage Roles in Server Settings > Roles.")
+        },
+    ];
+
+    match api::get_guild(&state(ctx).await.api(), guild_id).await {
+        Ok(guild) => {
+            checks.push(if guild.approved {
+                Check::pass("Registration", "This server is registered and approved.")
+            } else {
+                Check::fail("Registration", "This server is registered but hasn't been approved yet. Contact the ECSS web officer to get it approved.")
+            });
+            checks.push(check_role(
+                "Verified role",
+                guild.role_id,
+                guild_id,
+                &roles,
+                bot_position,
+            ));
+            if let Some(unverified) = guild.unverified_role_id {
+                checks.push(check_role(
+                    "Unverified role",
+                    unverified,
+                    guild_id,
+                    &roles,
+                    bot_position,
+                ));
+            }
+            if let Some(member) = guild.member_role_id {
+                checks.push(check_role(
+                    "Member role",
+                    member,
+                    guild_id,
+                    &roles,
+                    bot_position,
+                ));
+            }
+            if let Some(alumni) = guild.alumni_role_id {
+                checks.push(check_role(
+                    "Alumni role",
+                    alumni,
+                    guild_id,
+                    &roles,
+                    bot_position,
+                ));
+            }
+        }
+        Err(e) => checks.push(Check::fail("Registration", e.admin_message())),
+    }
+
+    checks.push(check_commands(ctx, guild_id).await);
+
+    let report = checks
+        .iter()
+        .map(|c| {
+            format!(
+                "{} **{}**: {}",
+                if c.passed { "✅" } else { "❌" },
+                c.name,
+                c.detail
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(report))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
+
+/// Checks that a role exists and is one the bot can hand out.
+fn check_role(
+    name: &'static str,
+    role_id: RoleId,
+    guild_id: GuildId,
+    roles: &HashMap<RoleId, Role>,
+    bot_position: u16,
+) -> Check {
+    let Some(role) = roles.get(&role_id) else {
+        return Check::fail(
+            name,
+            "The role no longer exists. Run /setup again with a new role.",
+        );
+    };
+    if role.id.get() == guild_id.get() {
+        Check::fail(
+            name,
+            "The role is @everyone. Run /setup again with a different role.",
+        )
+    } else if role.managed {
+        Check::fail(name, format!("{} is managed by an integration so I can't give it out. Run /setup again with a different role.", role.mention()))
+    } else if role.position >= bot_position {
+        Check::fail(
+            name,
+            format!(
+                "{} is above my highest role. Move my role above it in Server Settings > Roles.",
+                role.mention()
+            ),
+        )
+    } else {
+        Check::pass(
+            name,
+            format!("{} is below my highest role.", role.mention()),
+        )
+    }
+}
+
+/// Checks that every command the bot provides is registered either globally or in this guild.
+async fn check_commands(ctx: &Context, guild_id: GuildId) -> Check {
+    let registered = match (
+        Command::get_global_commands(ctx).await,
+        guild_id.get_commands(ctx).await,
+    ) {
+        (Ok(global), Ok(guild)) => global
+            .into_iter()
+            .chain(guild)
+            .map(|c| c.name)
+            .collect::<HashSet<_>>(),
+        (Err(e), _) | (_, Err(e)) => {
+            return Check::fail(
+                "Commands",
+                format!("I couldn't fetch the registered commands: {e}"),
+            )
+        }
+    };
+    let missing = create_commands()
+        .iter()
+        .filter_map(|c| {
+            serde_json::to_value(c)
+                .ok()
+                .and_then(|v| v["name"].as_str().map(str::to_string))
+        })
+        .filter(|name| !registered.contains(name))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Check::pass("Commands", "All of my commands are registered.")
+    } else {
+        Check::fail("Commands", format!("{} aren't registered. They can take up to an hour to appear, if they still don't show up let the bot's host know.", missing.join(", ")))
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use crate::commands::api::ApiError;
+
+/// How long to back off for when the verify service is down and didn't say when to come back.
+const UNAVAILABLE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What background verification should do after an API error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// Retrying won't help, so stop trying.
+    Never,
+    /// Keep retrying at the usual rate.
+    Retry,
+    /// Keep retrying but wait at least this long before hitting the API again.
+    Backoff(Duration),
+}
+
+impl ApiError {
+    /// Message to show a member that ran a command.
+    pub fn user_message(&self) -> String {
+        match self {
+            ApiError::NotVerified => "You haven't verified yourself yet.".to_string(),
+            ApiError::GuildNotRegistered => "It looks like your server doesn't support this bot, please contact the admins so they can run /setup.".to_string(),
+            ApiError::Unauthorized => "The bot isn't configured properly, please let the server admins know.".to_string(),
+            ApiError::BadRequest(_) | ApiError::InvalidResponse(_) => "Something went wrong while checking your verification, please try again later.".to_string(),
+            ApiError::AlreadyRegistered => "This server has already been set up.".to_string(),
+            ApiError::Unavailable(_) => "The verification service is currently unavailable, please try again in a few minutes.".to_string(),
+            ApiError::RateLimited(after) => format!(
+                "The verification service is busy, please try again in {}s.",
+                after.unwrap_or(UNAVAILABLE_BACKOFF).as_secs()
+            ),
+        }
+    }
+
+    /// Message to show an admin or moderator that ran a command.
+    pub fn admin_message(&self) -> String {
+        match self {
+            ApiError::NotVerified => "This user hasn't verified themselves yet.".to_string(),
+            ApiError::GuildNotRegistered => "It looks like your server doesn't support this bot, please run /setup.".to_string(),
+            ApiError::Unauthorized => "The verification service rejected the bot's API key, please contact the ECSS web officer.".to_string(),
+            ApiError::BadRequest(reason) => format!("The verification service rejected the request: {reason}"),
+            ApiError::AlreadyRegistered => "This server has already been registered, please contact the ECSS web officer to change its registration.".to_string(),
+            ApiError::InvalidResponse(_) => "The verification service sent a response the bot didn't understand, please contact the ECSS web officer.".to_string(),
+            ApiError::Unavailable(_) => "The verification service is currently unavailable, please try again later.".to_string(),
+            ApiError::RateLimited(after) => format!(
+                "The verification service is busy, please try again in {}s.",
+                after.unwrap_or(UNAVAILABLE_BACKOFF).as_secs()
+            ),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        match self {
+            ApiError::NotVerified => RetryPolicy::Retry,
+            ApiError::GuildNotRegistered
+            | ApiError::Unauthorized
+            | ApiError::BadRequest(_)
+            | ApiError::AlreadyRegistered => RetryPolicy::Never,
+            // Could be a response cut short, or the API being redeployed.
+            ApiError::Unavailable(_) | ApiError::InvalidResponse(_) => {
+                RetryPolicy::Backoff(UNAVAILABLE_BACKOFF)
+            }
+            ApiError::RateLimited(after) => {
+                RetryPolicy::Backoff(after.unwrap_or(UNAVAILABLE_BACKOFF))
+            }
+        }
+    }
+}
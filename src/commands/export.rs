@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use anyhow::{bail, Context as ContextTrait};
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    CommandInteraction, CreateAttachment, EditInteractionResponse, ResolvedOption, ResolvedValue,
+};
+use serenity::client::Context;
+use serenity::futures::{stream, StreamExt};
+use serenity::model::guild::Member;
+use serenity::model::prelude::{GuildId, RoleId, UserId};
+use serenity::model::Timestamp;
+
+use crate::commands::api::{self, ApiClient, ApiError};
+use crate::state::state;
+use crate::store;
+
+/// File each guild's export privacy settings are kept in.
+const PRIVACY: &str = "export.json";
+
+/// How many members to check with each batch verification request.
+const BATCH_SIZE: usize = 100;
+
+/// How many verification records to fetch at once when link dates are exported.
+const CONCURRENCY: usize = 10;
+
+/// Which of the optional columns a guild's exports include. IDs and verification status are
+/// always included as an export is useless without them.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Privacy {
+    /// The username and display name columns.
+    pub names: bool,
+    /// When the member linked their Discord account.
+    pub link_dates: bool,
+}
+
+impl Default for Privacy {
+    fn default() -> Privacy {
+        Privacy {
+            names: true,
+            link_dates: true,
+        }
+    }
+}
+
+/// Which members to export.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Filter {
+    All,
+    Verified,
+    Unverified,
+}
+
+/// A member as they appear in an export.
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub user_id: UserId,
+    pub username: String,
+    pub display_name: String,
+    pub has_role: bool,
+    pub verified: bool,
+    pub linked: Option<Timestamp>,
+}
+
+/// Quotes a CSV field if needed. Fields that spreadsheets would treat as formulas are prefixed
+/// with `'` as names are chosen by members.
+pub fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Builds the CSV for `rows`, leaving out the columns `privacy` excludes.
+pub fn to_csv(rows: &[Row], privacy: Privacy) -> String {
+    let yes_no = |b: bool| if b { "Yes" } else { "No" }.to_string();
+    let mut header = vec!["Discord ID"];
+    if privacy.names {
+        header.extend(["Username", "Display name"]);
+    }
+    header.extend(["Has verified role", "Verified"]);
+    if privacy.link_dates {
+        header.push("Discord linked date");
+    }
+
+    let mut csv = header.join(",") + "\r\n";
+    for row in rows {
+        let mut fields = vec![row.user_id.to_string()];
+        if privacy.names {
+            fields.extend([row.username.clone(), row.display_name.clone()]);
+        }
+        fields.extend([yes_no(row.has_role), yes_no(row.verified)]);
+        if privacy.link_dates {
+            fields.push(row.linked.map(|t| t.to_string()).unwrap_or_default());
+        }
+        let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Exports the guild's members as a CSV, or changes which columns exports include.
+pub async fn export(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let options = command.data.options();
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = options.first()
+    else {
+        bail!("Unable to get option info.");
+    };
+    match *name {
+        "members" => members(ctx, &command, options).await,
+        "privacy" => privacy(ctx, &command, options).await,
+        _ => bail!("Unknown export subcommand."),
+    }
+}
+
+async fn respond(ctx: &Context, command: &CommandInteraction, content: String) -> Result<()> {
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
+
+async fn privacy(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    let option = |name| {
+        options.iter().find_map(|o| match o.value {
+            ResolvedValue::Boolean(b) if o.name == name => Some(b),
+            _ => None,
+        })
+    };
+    let (names, link_dates) = (option("names"), option("link-dates"));
+    let privacy = store::update(PRIVACY, |settings: &mut HashMap<GuildId, Privacy>| {
+        let privacy = settings.entry(guild_id).or_default();
+        privacy.names = names.unwrap_or(privacy.names);
+        privacy.link_dates = link_dates.unwrap_or(privacy.link_dates);
+        *privacy
+    })
+    .await?;
+
+    let included = |b| if b { "included" } else { "left out" };
+    respond(
+        ctx,
+        command,
+        format!(
+            "Exports now include IDs and verification status. Usernames and display names are {}, link dates are {}.",
+            included(privacy.names),
+            included(privacy.link_dates),
+        ),
+    )
+    .await
+}
+
+/// Checks which members are verified, along with when they linked their accounts if `linked`.
+async fn verification(
+    api: &ApiClient,
+    guild_id: GuildId,
+    members: &[Member],
+    linked: bool,
+) -> Result<HashMap<UserId, Option<Timestamp>>, ApiError> {
+    let user_ids = members.iter().map(|m| m.user.id).collect::<Vec<_>>();
+    if !linked {
+        let mut verified = HashSet::new();
+        let mut batch_supported = true;
+        for chunk in user_ids.chunks(BATCH_SIZE) {
+            if batch_supported {
+                match api::batch_verified(api, chunk, guild_id).await? {
+                    Some(batch) => {
+                        verified.extend(batch);
+                        continue;
+                    }
+                    None => batch_supported = false,
+                }
+            }
+            for &user_id in chunk {
+                match api::is_verified(api, user_id, guild_id).await {
+                    Ok(()) => {
+                        verified.insert(user_id);
+                    }
+                    Err(ApiError::NotVerified) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        return Ok(verified.into_iter().map(|u| (u, None)).collect());
+    }
+
+    let records = stream::iter(user_ids)
+        .map(|user_id| async move { (user_id, api::get_verified(api, user_id, guild_id).await) })
+        .buffer_unordered(CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    let mut verified = HashMap::new();
+    for (user_id, record) in records {
+        match record {
+            Ok(record) if record.verified => {
+                verified.insert(user_id, Some(record.discord_linked_date));
+            }
+            Ok(_) | Err(ApiError::NotVerified) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(verified)
+}
+
+async fn members(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    let filter = options.iter().find_map(|o| match o.value {
+        ResolvedValue::String(s) if o.name == "status" => Some(s),
+        _ => None,
+    });
+    let filter = match filter {
+        Some("verified") => Filter::Verified,
+        Some("unverified") => Filter::Unverified,
+        _ => Filter::All,
+    };
+    let with_role: Option<RoleId> = options.iter().find_map(|o| match o.value {
+        ResolvedValue::Role(r) if o.name == "role" => Some(r.id),
+        _ => None,
+    });
+
+    let api = state(ctx).await.api();
+    let guild = match api::get_guild(&api, guild_id).await {
+        Ok(guild) => guild,
+        Err(e) => {
+            respond(ctx, command, e.admin_message()).await?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+    let settings: HashMap<GuildId, Privacy> = store::load(PRIVACY).await?;
+    let privacy = settings.get(&guild_id).copied().unwrap_or_default();
+
+    let mut members = vec![];
+    let mut iter = guild_id.members_iter(ctx).boxed();
+    while let Some(member) = iter.next().await {
+        let member = member.context(concat!(file!(), ":", line!()))?;
+        if member.user.bot || with_role.is_some_and(|r| !member.roles.contains(&r)) {
+            continue;
+        }
+        members.push(member);
+    }
+
+    let verified = match verification(&api, guild_id, &members, privacy.link_dates).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            respond(ctx, command, e.admin_message()).await?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+    let rows = members
+        .iter()
+        .map(|m| Row {
+            user_id: m.user.id,
+            username: m.user.name.clone(),
+            display_name: m.display_name().to_string(),
+            has_role: m.roles.contains(&guild.role_id),
+            verified: verified.contains_key(&m.user.id),
+            linked: verified.get(&m.user.id).copied().flatten(),
+        })
+        .filter(|r| match filter {
+            Filter::All => true,
+            Filter::Verified => r.verified,
+            Filter::Unverified => !r.verified,
+        })
+        .collect::<Vec<_>>();
+
+    let count = match rows.len() {
+        1 => "1 member".to_string(),
+        n => format!("{n} members"),
+    };
+    let csv = to_csv(&rows, privacy);
+    command
+        .edit_response(
+            ctx,
+            EditInteractionResponse::new()
+                .content(format!("Exported {count}."))
+                .new_attachment(CreateAttachment::bytes(
+                    csv.into_bytes(),
+                    format!("members-{guild_id}.csv"),
+                )),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serenity::model::prelude::{GuildId, UserId};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+/// Gets the url a user should go to in order to verify themselves.
+///
+/// When `LINK_SECRET` is set the url carries a token of the form `{user}.{guild}.{expiry}.{sig}`
+/// where `sig` is the unpadded url safe base64 HMAC-SHA256 of `{user}.{guild}.{expiry}`, letting
+/// the website link the right Discord account and know which guild the user came from.
+pub fn verification_url(config: &Config, user_id: UserId, guild_id: GuildId) -> String {
+    let Some(secret) = &config.link_secret else {
+        return config.display_url.to_string();
+    };
+    let expires = (SystemTime::now() + config.link_ttl)
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the unix epoch")
+        .as_secs();
+
+    let token = sign(secret.as_bytes(), user_id, guild_id, expires);
+    let mut url = config.display_url.clone();
+    url.query_pairs_mut().append_pair("token", &token);
+    url.into()
+}
+
+fn sign(secret: &[u8], user_id: UserId, guild_id: GuildId, expires: u64) -> String {
+    let payload = format!("{user_id}.{guild_id}.{expires}");
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    format!(
+        "{payload}.{}",
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use anyhow::{bail, Context as ContextTrait};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ButtonStyle, ChannelId, ChannelType, CommandInteraction, CreateActionRow, CreateButton,
+    CreateChannel, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse, EditRole, Mentionable, PermissionOverwrite, PermissionOverwriteType,
+    Permissions,
+};
+use serenity::client::Context;
+use serenity::model::prelude::{GuildId, RoleId};
+
+use crate::commands::api;
+use crate::commands::components::wait_for_component;
+use crate::state::state;
+use crate::store;
+
+/// File the changes made by each guild's lockdown are kept in, so they can be undone.
+const SNAPSHOTS: &str = "lockdown.json";
+
+/// Guilds an admin is currently applying or undoing a lockdown in.
+static IN_PROGRESS: Lazy<Mutex<HashSet<GuildId>>> = Lazy::new(Default::default);
+
+/// Marks a guild's lockdown as in progress until dropped.
+struct InProgress(GuildId);
+
+impl InProgress {
+    /// Returns `None` if someone else is already changing the guild's lockdown.
+    fn start(guild_id: GuildId) -> Option<InProgress> {
+        IN_PROGRESS
+            .lock()
+            .unwrap()
+            .insert(guild_id)
+            .then_some(InProgress(guild_id))
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        IN_PROGRESS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Everything a lockdown changed, and what it was before.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct Snapshot {
+    /// The permissions @everyone had, if we changed them.
+    everyone_permissions: Option<Permissions>,
+    /// A verified role we created.
+    created_role: Option<RoleId>,
+    /// An existing verified role we changed and the permissions it had.
+    edited_role: Option<(RoleId, Permissions)>,
+    created_channels: Vec<ChannelId>,
+}
+
+impl Snapshot {
+    fn is_empty(&self) -> bool {
+        self.everyone_permissions.is_none()
+            && self.created_role.is_none()
+            && self.edited_role.is_none()
+            && self.created_channels.is_empty()
+    }
+}
+
+/// What to do with the verified role.
+enum VerifiedPlan {
+    Keep(RoleId),
+    Edit(RoleId, Permissions),
+    Create,
+}
+
+struct Plan {
+    /// The current permissions of @everyone, if they can view channels.
+    everyone: Option<Permissions>,
+    verified: VerifiedPlan,
+    read_me: bool,
+    verify_yourself: bool,
+}
+
+/// Applies or reverts the permission changes recommended in setup.md.
+pub async fn lockdown(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    // Held until the changes are made so two admins can't interleave them.
+    let Some(_in_progress) = InProgress::start(command.guild_id.unwrap()) else {
+        return respond(
+            ctx,
+            &command,
+            "Another admin is already changing this server's lockdown, please wait for them to finish.",
+        )
+        .await;
+    };
+    match command.data.options.first().map(|o| o.name.as_str()) {
+        Some("apply") => apply(ctx, &command).await,
+        Some("undo") => undo(ctx, &command).await,
+        _ => bail!("Unknown lockdown subcommand."),
+    }
+}
+
+async fn respond(ctx: &Context, command: &CommandInteraction, content: &str) -> Result<()> {
+    command
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))
+}
+
+/// Shows a preview of the changes and waits for the admin to confirm them, returns whether they
+/// did.
+async fn confirm(ctx: &Context, command: &CommandInteraction, preview: String) -> Result<bool> {
+    command
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(preview)
+                    .components(vec![CreateActionRow::Buttons(vec![
+                        CreateButton::new("lockdown-confirm")
+                            .label("Confirm")
+                            .style(ButtonStyle::Danger),
+                        CreateButton::new("lockdown-cancel")
+                            .label("Cancel")
+                            .style(ButtonStyle::Secondary),
+                    ])])
+                    .ephemeral(true),
+            ),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+
+    let message = command
+        .get_response(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let press = wait_for_component(message.id, Duration::from_secs(60 * 5)).await;
+    let content = match press {
+        Some(press) if press.data.custom_id == "lockdown-confirm" => {
+            press
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new()
+                            .content("Working on it...")
+                            .components(vec![]),
+                    ),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            return Ok(true);
+        }
+        Some(_) => "Cancelled, nothing has been changed.",
+        None => "Timed out, nothing has been changed.",
+    };
+    command
+        .edit_response(
+            ctx,
+            EditInteractionResponse::new()
+                .content(content)
+                .components(vec![]),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(false)
+}
+
+async fn apply(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    let snapshots: HashMap<GuildId, Snapshot> = store::load(SNAPSHOTS).await?;
+    if snapshots.contains_key(&guild_id) {
+        return respond(
+            ctx,
+            command,
+            "This server has already been locked down, run /lockdown undo first if you want to do it again.",
+        )
+        .await;
+    }
+
+    let partial_guild =
+        guild_id
+            .to_partial_guild(ctx)
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+    let channels = guild_id
+        .channels(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let has_channel = |name: &str| channels.values().any(|c| c.name == name);
+    let everyone = partial_guild
+        .roles
+        .get(&RoleId::new(guild_id.get()))
+        .map_or(Permissions::empty(), |r| r.permissions);
+    let registered = api::get_guild(&state(ctx).await.api(), guild_id)
+        .await
+        .ok()
+        .and_then(|g| partial_guild.roles.get(&g.role_id));
+
+    let plan = Plan {
+        everyone: everyone.view_channel().then_some(everyone),
+        verified: match registered {
+            Some(role) if role.permissions.view_channel() => VerifiedPlan::Keep(role.id),
+            Some(role) => VerifiedPlan::Edit(role.id, role.permissions),
+            None => VerifiedPlan::Create,
+        },
+        read_me: !has_channel("read-me"),
+        verify_yourself: !has_channel("verify-yourself"),
+    };
+
+    let mut preview = vec!["This will:".to_string()];
+    match plan.verified {
+        VerifiedPlan::Keep(_) => {}
+        VerifiedPlan::Edit(role, _) => {
+            preview.push(format!("- Let {} view channels.", role.mention()))
+        }
+        VerifiedPlan::Create => preview.push(
+            "- Create a Verified role that can view channels, run /setup with it afterwards."
+                .to_string(),
+        ),
+    }
+    if plan.read_me {
+        preview.push(
+            "- Create #read-me where unverified members can read but not send messages."
+                .to_string(),
+        );
+    }
+    if plan.verify_yourself {
+        preview.push("- Create #verify-yourself where unverified members can run /verify, hidden from verified members.".to_string());
+    }
+    if plan.everyone.is_some() {
+        preview.push("- Stop @everyone from viewing channels.".to_string());
+    }
+    if preview.len() == 1 {
+        return respond(
+            ctx,
+            command,
+            "Your server already follows our recommendations, there is nothing to change.",
+        )
+        .await;
+    }
+    preview.push("\nYou can revert these changes at any time with /lockdown undo.".to_string());
+
+    if !confirm(ctx, command, preview.join("\n")).await? {
+        return Ok(());
+    }
+
+    let mut snapshot = Snapshot::default();
+    let result = execute(ctx, guild_id, &plan, &mut snapshot).await;
+    // Nothing to undo would only stop the lockdown being tried again.
+    let changed = !snapshot.is_empty();
+    if changed {
+        store::update(SNAPSHOTS, |s: &mut HashMap<GuildId, Snapshot>| {
+            s.insert(guild_id, snapshot)
+        })
+        .await?;
+    }
+
+    let content = match &result {
+        Ok(()) => "Your server has been locked down! Check everything looks right by viewing the server as the verified role and @everyone.".to_string(),
+        Err(e) if changed => format!("Something went wrong ({e}), run /lockdown undo to revert the changes made so far."),
+        Err(e) => format!("Something went wrong ({e}), nothing has been changed."),
+    };
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    result
+}
+
+/// Carries out a plan, recording every change in `snapshot` as it goes so a failure part way
+/// through can still be undone.
+async fn execute(
+    ctx: &Context,
+    guild_id: GuildId,
+    plan: &Plan,
+    snapshot: &mut Snapshot,
+) -> Result<()> {
+    let everyone_id = RoleId::new(guild_id.get());
+    // Give verified members access before taking it away from everyone else.
+    let verified = match plan.verified {
+        VerifiedPlan::Keep(role) => role,
+        VerifiedPlan::Edit(role, permissions) => {
+            guild_id
+                .edit_role(
+                    ctx,
+                    role,
+                    EditRole::new().permissions(permissions | Permissions::VIEW_CHANNEL),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            snapshot.edited_role = Some((role, permissions));
+            role
+        }
+        VerifiedPlan::Create => {
+            let role = guild_id
+                .create_role(
+                    ctx,
+                    EditRole::new()
+                        .name("Verified")
+                        .permissions(Permissions::VIEW_CHANNEL),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            snapshot.created_role = Some(role.id);
+            role.id
+        }
+    };
+
+    if plan.read_me {
+        let channel = guild_id
+            .create_channel(
+                ctx,
+                CreateChannel::new("read-me")
+                    .kind(ChannelType::Text)
+                    .permissions(vec![PermissionOverwrite {
+                        allow: Permissions::VIEW_CHANNEL,
+                        deny: Permissions::SEND_MESSAGES,
+                        kind: PermissionOverwriteType::Role(everyone_id),
+                    }]),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        snapshot.created_channels.push(channel.id);
+    }
+    if plan.verify_yourself {
+        let channel = guild_id
+            .create_channel(
+                ctx,
+                CreateChannel::new("verify-yourself")
+                    .kind(ChannelType::Text)
+                    .permissions(vec![
+                        PermissionOverwrite {
+                            allow: Permissions::VIEW_CHANNEL
+                                | Permissions::SEND_MESSAGES
+                                | Permissions::USE_APPLICATION_COMMANDS,
+                            deny: Permissions::empty(),
+                            kind: PermissionOverwriteType::Role(everyone_id),
+                        },
+                        PermissionOverwrite {
+                            allow: Permissions::empty(),
+                            deny: Permissions::VIEW_CHANNEL,
+                            kind: PermissionOverwriteType::Role(verified),
+                        },
+                    ]),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        snapshot.created_channels.push(channel.id);
+    }
+
+    if let Some(permissions) = plan.everyone {
+        guild_id
+            .edit_role(
+                ctx,
+                everyone_id,
+                EditRole::new().permissions(permissions - Permissions::VIEW_CHANNEL),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        snapshot.everyone_permissions = Some(permissions);
+    }
+    Ok(())
+}
+
+async fn undo(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    let mut snapshots: HashMap<GuildId, Snapshot> = store::load(SNAPSHOTS).await?;
+    let Some(snapshot) = snapshots.remove(&guild_id) else {
+        return respond(ctx, command, "This server hasn't been locked down.").await;
+    };
+
+    let mut preview = vec!["This will:".to_string()];
+    if snapshot.everyone_permissions.is_some() {
+        preview.push("- Restore the permissions @everyone had.".to_string());
+    }
+    if let Some((role, _)) = snapshot.edited_role {
+        preview.push(format!("- Restore the permissions {} had.", role.mention()));
+    }
+    if let Some(role) = snapshot.created_role {
+        preview.push(format!("- Delete the {} role.", role.mention()));
+    }
+    for channel in &snapshot.created_channels {
+        preview.push(format!("- Delete {}.", channel.mention()));
+    }
+
+    if !confirm(ctx, command, preview.join("\n")).await? {
+        return Ok(());
+    }
+
+    let result = revert(ctx, guild_id, &snapshot).await;
+    let content = match &result {
+        Ok(()) => {
+            store::update(SNAPSHOTS, |s: &mut HashMap<GuildId, Snapshot>| {
+                s.remove(&guild_id)
+            })
+            .await?;
+            "The lockdown has been undone.".to_string()
+        }
+        Err(e) => format!("Something went wrong ({e}), please try again."),
+    };
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    result
+}
+
+async fn revert(ctx: &Context, guild_id: GuildId, snapshot: &Snapshot) -> Result<()> {
+    // Restore @everyone first so nobody loses access while the verified role is reverted.
+    if let Some(permissions) = snapshot.everyone_permissions {
+        guild_id
+            .edit_role(
+                ctx,
+                RoleId::new(guild_id.get()),
+                EditRole::new().permissions(permissions),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+    }
+    for channel in &snapshot.created_channels {
+        // The channel may have already been deleted by hand.
+        if channel.to_channel(ctx).await.is_ok() {
+            channel
+                .delete(ctx)
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+        }
+    }
+    if let Some((role, permissions)) = snapshot.edited_role {
+        guild_id
+            .edit_role(ctx, role, EditRole::new().permissions(permissions))
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+    }
+    if let Some(role) = snapshot.created_role {
+        guild_id
+            .delete_role(ctx, role)
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Hands modal submissions to the command waiting for them. Submissions can arrive over the
+//! gateway or the interactions endpoint, so serenity's collectors, which only see gateway events,
+//! can't be used.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use serenity::all::ModalInteraction;
+use serenity::model::prelude::{GuildId, UserId};
+use tokio::sync::oneshot;
+
+struct Waiter {
+    guild_id: GuildId,
+    user_id: UserId,
+    custom_id: &'static str,
+    modal: oneshot::Sender<ModalInteraction>,
+}
+
+static WAITERS: Lazy<Mutex<Vec<Waiter>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Waits for `user_id` to submit the modal with `custom_id` in `guild_id`.
+pub async fn wait_for_modal(
+    guild_id: GuildId,
+    user_id: UserId,
+    custom_id: &'static str,
+    timeout: Duration,
+) -> Option<ModalInteraction> {
+    let (modal, recv) = oneshot::channel();
+    {
+        let mut waiters = WAITERS.lock().unwrap();
+        // Drop anyone who stopped waiting so the list doesn't grow forever.
+        waiters.retain(|w| !w.modal.is_closed());
+        waiters.push(Waiter {
+            guild_id,
+            user_id,
+            custom_id,
+            modal,
+        });
+    }
+    tokio::time::timeout(timeout, recv).await.ok()?.ok()
+}
+
+/// Passes a submitted modal to whoever is waiting for it, returning it if nobody is.
+pub fn deliver_modal(modal: ModalInteraction) -> Option<ModalInteraction> {
+    let waiter = {
+        let mut waiters = WAITERS.lock().unwrap();
+        let position = waiters.iter().position(|w| {
+            Some(w.guild_id) == modal.guild_id
+                && w.user_id == modal.user.id
+                && w.custom_id == modal.data.custom_id
+                && !w.modal.is_closed()
+        });
+        position.map(|i| waiters.remove(i))
+    };
+    match waiter {
+        Some(waiter) => waiter.modal.send(modal).err(),
+        None => Some(modal),
+    }
+}
@@ -0,0 +1,172 @@
+use std::num::NonZeroU64;
+
+use anyhow::Result;
+use anyhow::{bail, Context as ContextTrait};
+use cached::Cached;
+use serenity::all::{
+    CommandInteraction, CreateAttachment, EditInteractionResponse, ResolvedOption, ResolvedValue,
+};
+use serenity::client::Context;
+use serenity::model::prelude::GuildId;
+
+use crate::commands::api;
+use crate::commands::cleanup::alert_admins;
+use crate::state::state;
+
+/// Longest reply we'll send as a message before falling back to an attachment.
+const MAX_MESSAGE_LEN: usize = 1900;
+
+fn reply(content: String) -> EditInteractionResponse {
+    let message = EditInteractionResponse::new();
+    if content.len() > MAX_MESSAGE_LEN {
+        message
+            .content("The reply was too long for a message so it is attached.")
+            .new_attachment(CreateAttachment::bytes(content.into_bytes(), "reply.txt"))
+    } else {
+        message.content(content)
+    }
+}
+
+fn parse_guild_option(options: &[ResolvedOption]) -> Result<GuildId> {
+    match options.iter().find(|o| o.name == "guild").map(|o| &o.value) {
+        Some(ResolvedValue::String(id)) => Ok(GuildId::from(
+            id.trim()
+                .parse::<NonZeroU64>()
+                .context("That isn't a valid server id.")?,
+        )),
+        _ => bail!("Unable to get option info."),
+    }
+}
+
+/// Gets the guild option, telling the operator what was wrong with it if it isn't valid.
+async fn guild_option(
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<GuildId> {
+    match parse_guild_option(options) {
+        Ok(guild_id) => Ok(guild_id),
+        Err(e) => {
+            command
+                .edit_response(ctx, reply(e.to_string()))
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            Err(e)
+        }
+    }
+}
+
+/// Commands for the people running the bot, only usable by `OPERATOR_IDS` in the
+/// `OPERATOR_GUILD_ID` guild.
+pub async fn operator(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    // Most subcommands wait on the API or Discord, approving on both, before they can reply.
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let state = state(ctx).await;
+    if command.guild_id != state.config().operator_guild_id
+        || !state.config().operator_ids.contains(&command.user.id)
+    {
+        command
+            .edit_response(
+                ctx,
+                reply("You aren't allowed to use this command.".to_string()),
+            )
+            .await
+            .context(concat!(file!(), ":", line!()))?;
+        bail!("{} tried to use an operator command.", command.user.id);
+    }
+
+    let options = command.data.options();
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = options.first()
+    else {
+        bail!("Unable to get option info.");
+    };
+
+    let content = match *name {
+        "registrations" => {
+            let filter = options.iter().find_map(|o| match o.value {
+                ResolvedValue::String(s) if o.name == "status" => Some(s),
+                _ => None,
+            });
+            match api::list_guilds(&state.api()).await {
+                Ok(guilds) => {
+                    let lines = guilds
+                        .into_iter()
+                        .filter(|g| match filter {
+                            Some("pending") => !g.approved,
+                            Some("approved") => g.approved,
+                            _ => true,
+                        })
+                        .map(|g| {
+                            format!(
+                                "`{}` **{}** {}",
+                                g.guild_id,
+                                g.name,
+                                if g.approved { "approved" } else { "pending" }
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    if lines.is_empty() {
+                        "There are no matching registrations.".to_string()
+                    } else {
+                        lines.join("\n")
+                    }
+                }
+                Err(e) => e.admin_message(),
+            }
+        }
+        "approve" | "reject" => {
+            let approve = *name == "approve";
+            let guild_id = guild_option(ctx, &command, options).await?;
+            match api::review_guild(&state.api(), guild_id, approve).await {
+                Ok(()) => {
+                    {
+                        let mut cache = api::GET_GUILD.lock().await;
+                        cache.cache_remove(&guild_id);
+                    }
+                    if approve {
+                        alert_admins(
+                            ctx,
+                            guild_id,
+                            "Your server has been approved, members can now verify themselves!",
+                        )
+                        .await;
+                        format!("Approved `{guild_id}`.")
+                    } else {
+                        format!("Rejected `{guild_id}`.")
+                    }
+                }
+                Err(e) => e.admin_message(),
+            }
+        }
+        "refresh-cache" => {
+            api::GET_GUILD.lock().await.cache_clear();
+            api::IS_VERIFIED.lock().await.cache_clear();
+            "Cleared the guild and verification caches.".to_string()
+        }
+        "leave" => {
+            let guild_id = guild_option(ctx, &command, options).await?;
+            if let Err(e) = guild_id.leave(ctx).await {
+                command
+                    .edit_response(ctx, reply(format!("Couldn't leave `{guild_id}`: {e}")))
+                    .await
+                    .context(concat!(file!(), ":", line!()))?;
+                return Err(e).context(concat!(file!(), ":", line!()));
+            }
+            format!("Left `{guild_id}`.")
+        }
+        name => bail!("Unknown operator subcommand {name}."),
+    };
+
+    command
+        .edit_response(ctx, reply(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
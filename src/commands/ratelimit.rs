@@ -0,0 +1,70 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serenity::model::prelude::{GuildId, UserId};
+
+use crate::config::RateLimit;
+
+/// Who has run /verify recently, limited by `Config::verify_user_limit`.
+pub static VERIFY_USER_LIMIT: Lazy<RateLimiter<UserId>> = Lazy::new(RateLimiter::new);
+
+/// Where /verify has been run recently, limited by `Config::verify_guild_limit`.
+pub static VERIFY_GUILD_LIMIT: Lazy<RateLimiter<GuildId>> = Lazy::new(RateLimiter::new);
+
+/// Sliding window rate limiter, the limit is passed in with each hit so reloading the
+/// configuration can change it.
+pub struct RateLimiter<K> {
+    hits: Mutex<Hits<K>>,
+}
+
+struct Hits<K> {
+    by_key: HashMap<K, VecDeque<Instant>>,
+    /// When keys that have gone quiet were last forgotten.
+    swept: Instant,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new() -> Self {
+        Self {
+            hits: Mutex::new(Hits {
+                by_key: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Records a hit for `key` if it is within `limit`, otherwise returns how long it has to wait
+    /// before it is allowed another.
+    pub fn try_acquire(&self, key: K, limit: RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let expired = |t: &Instant| now.duration_since(*t) >= limit.window;
+        let mut hits = self.hits.lock().unwrap();
+        // Only the key being hit is pruned each time, everyone else is swept once per window.
+        if expired(&hits.swept) {
+            hits.by_key
+                .retain(|_, h| h.back().is_some_and(|t| !expired(t)));
+            hits.swept = now;
+        }
+
+        let key_hits = hits.by_key.entry(key).or_default();
+        while key_hits.front().is_some_and(expired) {
+            key_hits.pop_front();
+        }
+        if key_hits.len() >= limit.limit {
+            let oldest = key_hits.front().copied().unwrap_or(now);
+            return Err(limit.window.saturating_sub(now.duration_since(oldest)));
+        }
+        key_hits.push_back(now);
+        Ok(())
+    }
+
+    /// Takes back the latest hit for `key`, such as when another limit stopped it being used.
+    pub fn release(&self, key: &K) {
+        if let Some(key_hits) = self.hits.lock().unwrap().by_key.get_mut(key) {
+            key_hits.pop_back();
+        }
+    }
+}
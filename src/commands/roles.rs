@@ -0,0 +1,193 @@
+//! Every change to a member's roles goes through a queue per guild, so bursts from /verify-all
+//! and join waves are spread out rather than running into Discord's rate limits.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as ContextTrait, Result};
+use once_cell::sync::Lazy;
+use serenity::all::{EditMember, HttpError};
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::model::prelude::{GuildId, RoleId, UserId};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tracing::{info_span, warn, Instrument, Span};
+
+use crate::state::{state, AppState};
+
+/// How many times a change is attempted before giving up.
+const ATTEMPTS: u32 = 5;
+
+static QUEUES: Lazy<Mutex<HashMap<GuildId, UnboundedSender<Job>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A change to a member's roles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RoleChange {
+    Add(UserId, RoleId),
+    Remove(UserId, RoleId),
+    /// Replaces `remove` with `add` in a single request so the member never holds both or neither.
+    Swap {
+        user_id: UserId,
+        remove: RoleId,
+        add: RoleId,
+    },
+}
+
+impl RoleChange {
+    fn user_id(self) -> UserId {
+        match self {
+            RoleChange::Add(user_id, _) | RoleChange::Remove(user_id, _) => user_id,
+            RoleChange::Swap { user_id, .. } => user_id,
+        }
+    }
+}
+
+type Outcome = Result<(), Arc<serenity::Error>>;
+
+struct Job {
+    change: RoleChange,
+    done: oneshot::Sender<Outcome>,
+    /// The span of whatever asked for the change, so the request shows up under it.
+    span: Span,
+}
+
+/// Queues a change to a member's roles, waiting until it has been made.
+pub async fn change_roles(ctx: &Context, guild_id: GuildId, change: RoleChange) -> Result<()> {
+    let (done, outcome) = oneshot::channel();
+    let state = state(ctx).await;
+    QUEUES
+        .lock()
+        .unwrap()
+        .entry(guild_id)
+        .or_insert_with(|| {
+            let (send, recv) = unbounded_channel();
+            tokio::spawn(run_queue(ctx.http.clone(), state, guild_id, recv));
+            send
+        })
+        .send(Job {
+            change,
+            done,
+            span: Span::current(),
+        })
+        .ok();
+    match outcome.await {
+        Ok(outcome) => outcome.context(format!("Could not make {change:?} in guild {guild_id}.")),
+        Err(_) => Err(anyhow!("The role queue for guild {guild_id} stopped.")),
+    }
+}
+
+/// Stops the guild's queue once it has finished the changes already queued.
+pub fn forget_queue(guild_id: GuildId) {
+    QUEUES.lock().unwrap().remove(&guild_id);
+}
+
+type Pending = VecDeque<(RoleChange, Vec<oneshot::Sender<Outcome>>, Span)>;
+
+fn enqueue(pending: &mut Pending, job: Job) {
+    // Only merge with the user's latest change so an add, remove, add still ends with the role.
+    match pending
+        .iter_mut()
+        .rev()
+        .find(|(change, _, _)| change.user_id() == job.change.user_id())
+    {
+        Some((change, waiting, _)) if *change == job.change => waiting.push(job.done),
+        _ => pending.push_back((job.change, vec![job.done], job.span)),
+    }
+}
+
+async fn run_queue(
+    http: Arc<Http>,
+    state: Arc<AppState>,
+    guild_id: GuildId,
+    mut jobs: UnboundedReceiver<Job>,
+) {
+    let mut pending = Pending::new();
+    loop {
+        if pending.is_empty() {
+            match jobs.recv().await {
+                Some(job) => enqueue(&mut pending, job),
+                None => return,
+            }
+        }
+        while let Ok(job) = jobs.try_recv() {
+            enqueue(&mut pending, job);
+        }
+
+        let Some((change, waiting, span)) = pending.pop_front() else {
+            continue;
+        };
+        let span = info_span!(parent: &span, "role_change", guild = %guild_id, change = ?change);
+        let outcome = apply_with_retries(&http, guild_id, change)
+            .instrument(span)
+            .await
+            .map_err(Arc::new);
+        for done in waiting {
+            done.send(outcome.clone()).ok();
+        }
+        // Read each time so reloading the configuration can change it.
+        tokio::time::sleep(state.config().role_change_interval).await;
+    }
+}
+
+/// Whether a request failed because Discord is rate limiting us or struggling.
+fn is_transient(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            let status = response.status_code.as_u16();
+            status == 429 || status >= 500
+        }
+        _ => false,
+    }
+}
+
+async fn apply_with_retries(
+    http: &Http,
+    guild_id: GuildId,
+    change: RoleChange,
+) -> serenity::Result<()> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        match apply(http, guild_id, change).await {
+            Err(e) if attempt < ATTEMPTS && is_transient(&e) => {
+                warn!("Retrying {change:?} in guild {guild_id} in {delay:?}. {e:?}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn apply(http: &Http, guild_id: GuildId, change: RoleChange) -> serenity::Result<()> {
+    match change {
+        RoleChange::Add(user_id, role_id) => {
+            http.add_member_role(guild_id, user_id, role_id, None).await
+        }
+        RoleChange::Remove(user_id, role_id) => {
+            http.remove_member_role(guild_id, user_id, role_id, None)
+                .await
+        }
+        RoleChange::Swap {
+            user_id,
+            remove,
+            add,
+        } => {
+            let member = http.get_member(guild_id, user_id).await?;
+            let mut roles: Vec<RoleId> = member
+                .roles
+                .into_iter()
+                .filter(|r| *r != remove && *r != add)
+                .collect();
+            roles.push(add);
+            guild_id
+                .edit_member(http, user_id, EditMember::new().roles(roles))
+                .await?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Context as ContextTrait;
+use anyhow::Result;
+use serenity::all::{
+    CommandInteraction, EditInteractionResponse, FormattedTimestamp, FormattedTimestampStyle,
+    Timestamp,
+};
+use serenity::client::Context;
+use serenity::futures::StreamExt;
+
+use crate::commands::api;
+use crate::history::{self, Event, Method};
+use crate::state::state;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// What the recorded history says about verifications in a guild.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub last_week: usize,
+    pub last_month: usize,
+    pub by_method: HashMap<Method, usize>,
+    /// Only members whose join was recorded are counted.
+    pub median_wait: Option<Duration>,
+    /// When the first event was recorded.
+    pub since: Option<Timestamp>,
+}
+
+impl Stats {
+    pub fn from_events(events: &[Event], now: Timestamp) -> Stats {
+        let now = now.unix_timestamp();
+        let mut joined = HashMap::new();
+        let mut waits = vec![];
+        let mut stats = Stats {
+            last_week: 0,
+            last_month: 0,
+            by_method: HashMap::new(),
+            median_wait: None,
+            since: None,
+        };
+        for event in events {
+            match *event {
+                Event::Joined { user_id, at } => {
+                    stats.since.get_or_insert(at);
+                    joined.insert(user_id, at.unix_timestamp());
+                }
+                Event::Verified {
+                    user_id,
+                    at,
+                    method,
+                } => {
+                    stats.since.get_or_insert(at);
+                    let at = at.unix_timestamp();
+                    if now - at <= 7 * DAY {
+                        stats.last_week += 1;
+                    }
+                    if now - at <= 30 * DAY {
+                        stats.last_month += 1;
+                    }
+                    *stats.by_method.entry(method).or_default() += 1;
+                    // Timed from their latest join, so rejoining members count from coming back.
+                    if let Some(joined) = joined.remove(&user_id) {
+                        waits.push((at - joined).max(0));
+                    }
+                }
+            }
+        }
+
+        waits.sort_unstable();
+        let middle = waits.len() / 2;
+        stats.median_wait = match waits.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((waits[middle - 1] + waits[middle]) / 2),
+            _ => Some(waits[middle]),
+        }
+        .map(|secs| Duration::from_secs(secs as u64));
+        stats
+    }
+}
+
+/// Formats a duration using its two largest units, such as `2h 5m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let parts = units
+        .iter()
+        .skip_while(|(n, _)| *n == 0)
+        .take(2)
+        .filter(|(n, _)| *n != 0)
+        .map(|(n, unit)| format!("{n}{unit}"))
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Shows an admin how many members are verified and how they got there.
+pub async fn stats(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    command
+        .defer_ephemeral(ctx)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+
+    let guild = match api::get_guild(&state(ctx).await.api(), guild_id).await {
+        Ok(guild) => guild,
+        Err(e) => {
+            command
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new().content(e.admin_message()),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+
+    let (mut total, mut verified) = (0, 0);
+    let mut members = guild_id.members_iter(ctx).boxed();
+    while let Some(member) = members.next().await {
+        let member = member.context(concat!(file!(), ":", line!()))?;
+        if member.user.bot {
+            continue;
+        }
+        total += 1;
+        if member.roles.contains(&guild.role_id) {
+            verified += 1;
+        }
+    }
+
+    let events = history::load(guild_id)
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    let stats = Stats::from_events(&events, Timestamp::now());
+    let method = |m| stats.by_method.get(&m).copied().unwrap_or_default();
+    let median = stats
+        .median_wait
+        .map_or("No recorded joins yet".to_string(), format_duration);
+    let since = stats
+        .since
+        .map_or("Nothing has been recorded yet.".to_string(), |at| {
+            format!(
+                "Recorded since {}.",
+                FormattedTimestamp::new(at, Some(FormattedTimestampStyle::LongDate))
+            )
+        });
+
+    let content = format!(
+        "**Members:** {total} ({verified} verified, {} unverified)\n**Verified in the last 7 days:** {}\n**Verified in the last 30 days:** {}\n**How members verified in the last 30 days:** {} on joining, {} with /verify, {} with /verify-all, {} by moderators\n**Median time from joining to verifying in the last 30 days:** {median}\n{since}",
+        total - verified,
+        stats.last_week,
+        stats.last_month,
+        method(Method::Join),
+        method(Method::Command),
+        method(Method::VerifyAll),
+        method(Method::Moderator),
+    );
+    command
+        .edit_response(ctx, EditInteractionResponse::new().content(content))
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
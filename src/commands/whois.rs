@@ -0,0 +1,98 @@
+use anyhow::Result;
+use anyhow::{anyhow, Context as ContextTrait};
+use serenity::all::{
+    CommandInteraction, CreateInteractionResponse, CreateInteractionResponseMessage,
+    FormattedTimestamp, FormattedTimestampStyle, Mentionable, Timestamp,
+};
+use serenity::client::Context;
+
+use crate::commands::api;
+use crate::commands::api::{ApiError, Status};
+use crate::state::state;
+
+fn format_date(date: Timestamp) -> String {
+    FormattedTimestamp::new(date, Some(FormattedTimestampStyle::LongDateTime)).to_string()
+}
+
+/// Shows an admin everything we know about a user's verification.
+pub async fn whois(ctx: &Context, command: CommandInteraction) -> Result<()> {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command
+        .data
+        .options
+        .iter()
+        .find(|o| o.name == "user")
+        .and_then(|o| o.value.as_user_id())
+        .ok_or_else(|| anyhow!("Unable to get option info."))?;
+
+    let state = state(ctx).await;
+    let role = match api::get_guild(&state.api(), guild_id).await {
+        Ok(guild) => guild.role_id,
+        Err(e) => {
+            command
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(e.admin_message())
+                            .ephemeral(true),
+                    ),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+
+    let has_role = match guild_id.member(ctx, user_id).await {
+        Ok(member) if member.roles.contains(&role) => "Yes",
+        Ok(_) => "No",
+        Err(_) => "Not a member of this server",
+    };
+
+    let content = match api::get_verified(&state.api(), user_id, guild_id).await {
+        Ok(verified) => format!(
+            "**User:** {}\n**Verified:** {}\n**Status:** {}\n**University account linked:** {}\n**Discord account linked:** {}\n**Has verified role:** {has_role}",
+            user_id.mention(),
+            if verified.verified { "Yes" } else { "No" },
+            match verified.status {
+                Status::Student => "Student",
+                Status::Graduate => "Graduate",
+                Status::Unknown => "Unknown",
+            },
+            format_date(verified.soton_linked_date),
+            format_date(verified.discord_linked_date),
+        ),
+        Err(ApiError::NotVerified) => format!(
+            "**User:** {}\n**Verified:** No, no verification record was found\n**Has verified role:** {has_role}",
+            user_id.mention(),
+        ),
+        Err(e) => {
+            command
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(e.admin_message())
+                            .ephemeral(true),
+                    ),
+                )
+                .await
+                .context(concat!(file!(), ":", line!()))?;
+            return Err(e).context(concat!(file!(), ":", line!()));
+        }
+    };
+
+    command
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+        .context(concat!(file!(), ":", line!()))?;
+    Ok(())
+}
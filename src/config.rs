@@ -0,0 +1,181 @@
+//! Settings read from the environment when the bot starts.
+
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context as ContextTrait, Result};
+use ed25519_dalek::VerifyingKey;
+use reqwest::Url;
+use serenity::model::prelude::{GuildId, UserId};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub discord_token: String,
+    pub api_key: String,
+    pub api_url: String,
+    /// Where users are sent to verify themselves.
+    pub display_url: Url,
+    /// Secret shared with the website used to sign verification links.
+    pub link_secret: Option<String>,
+    /// How long signed verification links can be used for.
+    pub link_ttl: Duration,
+    /// Only register commands in this guild rather than globally.
+    pub test_guild_id: Option<GuildId>,
+    /// Guilds to remove leftover guild commands from.
+    pub purge_guild_ids: Vec<GuildId>,
+    /// The guild operator commands are registered in.
+    pub operator_guild_id: Option<GuildId>,
+    /// Users allowed to use operator commands.
+    pub operator_ids: Vec<UserId>,
+    /// How often a single user can run /verify.
+    pub verify_user_limit: RateLimit,
+    /// How often /verify can be run in a single guild, stops a raid from flooding the API.
+    pub verify_guild_limit: RateLimit,
+    /// Time to wait between role changes in the same guild.
+    pub role_change_interval: Duration,
+    /// How long to keep retrying verification for someone who hasn't verified yet.
+    pub retry_horizon: Duration,
+    /// How often to bring every member's roles up to date with the API.
+    pub sync_interval: Duration,
+    /// Where to listen for interactions sent by Discord instead of receiving them over the gateway.
+    pub interactions_addr: Option<SocketAddr>,
+    /// The application's public key, used to check interactions really came from Discord.
+    pub public_key: Option<VerifyingKey>,
+    /// Where files such as the join and verification history are kept.
+    pub data_dir: PathBuf,
+}
+
+/// Allows `limit` uses within any `window`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: usize,
+    pub window: Duration,
+}
+
+/// Environment variables by name.
+pub type Vars = HashMap<String, String>;
+
+/// Reads a variable, treating an empty value as unset.
+fn optional(vars: &Vars, key: &str) -> Option<String> {
+    vars.get(key).filter(|v| !v.trim().is_empty()).cloned()
+}
+
+fn required(vars: &Vars, key: &str) -> Result<String> {
+    optional(vars, key).ok_or_else(|| anyhow!("{key} environment var has not been set"))
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .trim()
+        .parse()
+        .context(format!("{key} has an invalid value: {value}"))
+}
+
+fn id_list<T: From<NonZeroU64>>(vars: &Vars, key: &str) -> Result<Vec<T>> {
+    optional(vars, key)
+        .iter()
+        .flat_map(|ids| ids.split(','))
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| parse::<NonZeroU64>(key, id).map(T::from))
+        .collect()
+}
+
+fn public_key(vars: &Vars, key: &str) -> Result<Option<VerifyingKey>> {
+    let Some(value) = optional(vars, key) else {
+        return Ok(None);
+    };
+    let bytes = hex::decode(value.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow!("{key} must be 64 hex characters: {value}"))?;
+    Ok(Some(
+        VerifyingKey::from_bytes(&bytes).context(format!("{key} is not a valid public key"))?,
+    ))
+}
+
+/// Reads the limit and window in seconds from `{prefix}_LIMIT` and `{prefix}_WINDOW`.
+fn rate_limit(vars: &Vars, prefix: &str, limit: usize, window: u64) -> Result<RateLimit> {
+    let limit_key = format!("{prefix}_LIMIT");
+    let window_key = format!("{prefix}_WINDOW");
+    let rate_limit = RateLimit {
+        limit: match optional(vars, &limit_key) {
+            Some(limit) => parse(&limit_key, &limit)?,
+            None => limit,
+        },
+        window: Duration::from_secs(match optional(vars, &window_key) {
+            Some(window) => parse(&window_key, &window)?,
+            None => window,
+        }),
+    };
+    // Either being 0 would silently turn the limit off.
+    ensure!(rate_limit.limit > 0, "{limit_key} must be at least 1");
+    ensure!(
+        !rate_limit.window.is_zero(),
+        "{window_key} must be at least 1"
+    );
+    Ok(rate_limit)
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config> {
+        Config::from_vars(&env::vars().collect())
+    }
+
+    /// Builds the configuration from `vars` rather than the process environment.
+    pub fn from_vars(vars: &Vars) -> Result<Config> {
+        let interactions_addr = optional(vars, "INTERACTIONS_ADDR")
+            .map(|addr| parse("INTERACTIONS_ADDR", &addr))
+            .transpose()?;
+        let public_key = public_key(vars, "DISCORD_PUBLIC_KEY")?;
+        if interactions_addr.is_some() && public_key.is_none() {
+            bail!("DISCORD_PUBLIC_KEY must be set to use INTERACTIONS_ADDR");
+        }
+
+        Ok(Config {
+            discord_token: required(vars, "DISCORD_TOKEN")?,
+            api_key: required(vars, "API_KEY")?,
+            api_url: required(vars, "API_URL")?,
+            display_url: parse("DISPLAY_URL", &required(vars, "DISPLAY_URL")?)?,
+            link_secret: optional(vars, "LINK_SECRET"),
+            link_ttl: match optional(vars, "LINK_TTL") {
+                Some(ttl) => Duration::from_secs(parse("LINK_TTL", &ttl)?),
+                None => Duration::from_secs(60 * 15),
+            },
+            test_guild_id: optional(vars, "TEST_GUILD_ID")
+                .map(|id| parse::<NonZeroU64>("TEST_GUILD_ID", &id).map(GuildId::from))
+                .transpose()?,
+            purge_guild_ids: id_list(vars, "PURGE_GUILD_IDS")?,
+            operator_guild_id: optional(vars, "OPERATOR_GUILD_ID")
+                .map(|id| parse::<NonZeroU64>("OPERATOR_GUILD_ID", &id).map(GuildId::from))
+                .transpose()?,
+            operator_ids: id_list(vars, "OPERATOR_IDS")?,
+            verify_user_limit: rate_limit(vars, "VERIFY_USER", 1, 10)?,
+            verify_guild_limit: rate_limit(vars, "VERIFY_GUILD", 30, 60)?,
+            role_change_interval: match optional(vars, "ROLE_CHANGE_INTERVAL") {
+                Some(interval) => Duration::from_millis(parse("ROLE_CHANGE_INTERVAL", &interval)?),
+                None => Duration::from_millis(250),
+            },
+            retry_horizon: match optional(vars, "VERIFY_RETRY_HORIZON") {
+                Some(horizon) => Duration::from_secs(parse("VERIFY_RETRY_HORIZON", &horizon)?),
+                None => Duration::from_secs(60 * 60),
+            },
+            sync_interval: match optional(vars, "SYNC_INTERVAL") {
+                Some(interval) => Duration::from_secs(parse("SYNC_INTERVAL", &interval)?),
+                None => Duration::from_secs(6 * 60 * 60),
+            },
+            interactions_addr,
+            public_key,
+            data_dir: PathBuf::from(
+                optional(vars, "DATA_DIR").unwrap_or_else(|| "data".to_string()),
+            ),
+        })
+    }
+}
@@ -0,0 +1,128 @@
+//! A record of the joins and verifications in each guild, kept in `DATA_DIR` so /stats has
+//! something to report on.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{GuildId, UserId};
+use serenity::model::Timestamp;
+use tracing::warn;
+
+use crate::store;
+
+/// How a member came to be verified.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// Automatically when they joined, including the retries afterwards.
+    Join,
+    /// By running /verify, including the retries afterwards.
+    Command,
+    VerifyAll,
+    /// By a moderator using the Verify this user or Check verification context menus.
+    Moderator,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Joined {
+        user_id: UserId,
+        at: Timestamp,
+    },
+    Verified {
+        user_id: UserId,
+        at: Timestamp,
+        method: Method,
+    },
+}
+
+/// How long events are kept in seconds, the longest window /stats reports on.
+const RETENTION: i64 = 30 * 24 * 60 * 60;
+
+impl Event {
+    fn at(&self) -> Timestamp {
+        match *self {
+            Event::Joined { at, .. } | Event::Verified { at, .. } => at,
+        }
+    }
+}
+
+/// Drops events older than `RETENTION` so the history doesn't grow forever.
+pub fn prune(events: &mut Vec<Event>, now: Timestamp) {
+    let cutoff = now.unix_timestamp() - RETENTION;
+    events.retain(|e| e.at().unix_timestamp() >= cutoff);
+}
+
+fn file(guild_id: GuildId) -> String {
+    format!("history/{guild_id}.json")
+}
+
+/// Gets every event recorded in a guild within `RETENTION`, oldest first.
+pub async fn load(guild_id: GuildId) -> anyhow::Result<Vec<Event>> {
+    let mut events = store::load(&file(guild_id)).await?;
+    prune(&mut events, Timestamp::now());
+    Ok(events)
+}
+
+async fn record(guild_id: GuildId, f: impl FnOnce(&mut Vec<Event>)) {
+    let update = |events: &mut Vec<Event>| {
+        prune(events, Timestamp::now());
+        f(events)
+    };
+    if let Err(e) = store::update(&file(guild_id), update).await {
+        warn!("Could not record history for guild {guild_id}. {e:?}");
+    }
+}
+
+/// Records that a member joined a guild.
+pub async fn record_joined(guild_id: GuildId, user_id: UserId) {
+    record(guild_id, |events| {
+        events.push(Event::Joined {
+            user_id,
+            at: Timestamp::now(),
+        })
+    })
+    .await;
+}
+
+/// Records that members were verified, skipping anyone already verified since they last joined
+/// as the role is often handed out again to people who already hold it. Only the kept history is
+/// checked, so someone verified longer than `RETENTION` ago is recorded again.
+pub async fn record_verified(guild_id: GuildId, user_ids: &[UserId], method: Method) {
+    if user_ids.is_empty() {
+        return;
+    }
+    record(guild_id, |events| {
+        let at = Timestamp::now();
+        let wanted: HashSet<_> = user_ids.iter().collect();
+        // Whether each member has been verified since they last joined.
+        let mut verified = HashMap::new();
+        for event in events.iter() {
+            match *event {
+                Event::Joined { user_id, .. } if wanted.contains(&user_id) => {
+                    verified.insert(user_id, false);
+                }
+                Event::Verified { user_id, .. } if wanted.contains(&user_id) => {
+                    verified.insert(user_id, true);
+                }
+                _ => {}
+            }
+        }
+        for &user_id in user_ids {
+            if verified.insert(user_id, true) != Some(true) {
+                events.push(Event::Verified {
+                    user_id,
+                    at,
+                    method,
+                });
+            }
+        }
+    })
+    .await;
+}
+
+/// Deletes a guild's history once the bot has left it.
+pub async fn forget(guild_id: GuildId) {
+    if let Err(e) = store::remove(&file(guild_id)).await {
+        warn!("Could not delete the history for guild {guild_id}. {e:?}");
+    }
+}
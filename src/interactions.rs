@@ -0,0 +1,80 @@
+//! Serves the interactions Discord sends to the application's interactions endpoint url, so
+//! commands don't need to come over the gateway. The gateway connection is still used for member
+//! join events.
+
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde_json::json;
+use serenity::all::Interaction;
+use serenity::client::Context;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::handle_interaction;
+use crate::state::state;
+
+/// How long Discord waits for a reply, commands respond through the callback endpoint themselves
+/// so we wait at most this long for them before replying.
+const RESPONSE_WINDOW: Duration = Duration::from_millis(2500);
+
+/// Serves interactions posted to `/interactions` until the listener fails.
+pub async fn serve(ctx: Context, listener: TcpListener) {
+    let app = Router::new()
+        .route("/interactions", post(interaction))
+        .with_state(ctx);
+    if let Ok(addr) = listener.local_addr() {
+        info!("Listening for interactions on {addr}.");
+    }
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Interactions endpoint stopped: {e:?}");
+    }
+}
+
+/// Checks an interaction was signed with the application's key, as Discord requires.
+pub fn verify_signature(public_key: &VerifyingKey, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let (Some(signature), Some(timestamp)) = (
+        header("X-Signature-Ed25519"),
+        header("X-Signature-Timestamp"),
+    ) else {
+        return false;
+    };
+    let Some(signature) = hex::decode(signature)
+        .ok()
+        .and_then(|s| Signature::from_slice(&s).ok())
+    else {
+        return false;
+    };
+    let message = [timestamp.as_bytes(), body].concat();
+    public_key.verify(&message, &signature).is_ok()
+}
+
+async fn interaction(State(ctx): State<Context>, headers: HeaderMap, body: Bytes) -> Response {
+    // The key is read for every request so reloading the configuration can change it.
+    let public_key = state(&ctx).await.config().public_key;
+    if !public_key.is_some_and(|key| verify_signature(&key, &headers, &body)) {
+        return (StatusCode::UNAUTHORIZED, "Invalid request signature").into_response();
+    }
+    let interaction = match serde_json::from_slice::<Interaction>(&body) {
+        Ok(interaction) => interaction,
+        Err(e) => {
+            warn!("Could not parse interaction: {e:?}");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    if let Interaction::Ping(_) = interaction {
+        return Json(json!({ "type": 1 })).into_response();
+    }
+    let handled = tokio::spawn(async move { handle_interaction(&ctx, interaction).await });
+    // Whatever isn't done by now carries on in the background.
+    tokio::time::timeout(RESPONSE_WINDOW, handled).await.ok();
+    StatusCode::ACCEPTED.into_response()
+}
@@ -1,26 +1,55 @@
 use anyhow::{anyhow, Context as ContextTrait, Result};
-use std::collections::HashMap;
-use std::time::Duration;
-use std::{env, mem};
-
-use futures::stream::FuturesUnordered;
-use futures::StreamExt;
-use log::{info, warn};
-use once_cell::sync::OnceCell;
-use serenity::all::{Command, CommandInteraction, CommandOptionType, CreateCommand, Interaction};
+use clap::Parser;
+use std::env;
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CommandType, CreateCommand, CreateInteractionResponse,
+    CreateInteractionResponseMessage, Interaction,
+};
 use serenity::async_trait;
 use serenity::builder::CreateCommandOption;
+use serenity::http::Http;
 use serenity::model::gateway::Ready;
-use serenity::model::guild::Member;
+use serenity::model::guild::{Guild, Member, Role, UnavailableGuild};
 use serenity::model::id::GuildId;
-use serenity::model::prelude::UserId;
+use serenity::model::prelude::RoleId;
+use serenity::model::user::User;
 use serenity::model::Permissions;
 use serenity::prelude::*;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::net::TcpListener;
+use tracing::{info, info_span, warn, Instrument};
 
-use crate::commands::{setup, silent_verify, verify, verify_all};
+use crate::cli::{Cli, Command};
+use crate::commands::{
+    audit, check_verification, deliver_component, deliver_modal, diagnose, export, guild_left,
+    lockdown, mark_unverified, operator, role_deleted, role_updated, setup, silent_verify, stats,
+    verify, verify_all, verify_user, whois, ApiClient,
+};
+use crate::config::{Config, Vars};
+use crate::history::Method;
+use crate::registration::{purge_guild_commands, sync_commands, Scope, Summary};
+use crate::scheduler::{check_for_verify, Task};
+use crate::state::{state, AppState};
+use crate::telemetry::next_id;
 
+mod cli;
 mod commands;
+mod config;
+mod history;
+mod interactions;
+mod registration;
+mod reload;
+mod scheduler;
+mod state;
+mod store;
+mod sync;
+mod telemetry;
+#[cfg(test)]
+mod tests;
+
+/// `GUILDS` is needed for the guild and role events cleanup relies on.
+const INTENTS: GatewayIntents = GatewayIntents::GUILDS.union(GatewayIntents::GUILD_MEMBERS);
 
 fn create_commands() -> Vec<CreateCommand> {
     vec![
@@ -42,59 +71,364 @@ fn create_commands() -> Vec<CreateCommand> {
                     "The role you will be using to mark people as verified.",
                 )
                 .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "unverified-role",
+                "An optional role given to members until they are verified.",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "member-role",
+                "An optional role for verified members who have paid for society membership.",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "alumni-role",
+                "An optional role the verified role is swapped for once a member graduates.",
+            )),
+        CreateCommand::new("diagnose")
+            .description("Checks that the bot is set up correctly.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR),
+        CreateCommand::new("lockdown")
+            .description("Applies the recommended verification permissions to your server.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "apply",
+                "Locks the server down so only verified members can see it.",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "undo",
+                "Reverts the changes made by /lockdown apply.",
+            )),
+        CreateCommand::new("audit")
+            .description("Lists the channels unverified members can access.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR),
+        CreateCommand::new("stats")
+            .description("Shows how many members are verified and how they verified.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR),
+        CreateCommand::new("export")
+            .description("Exports the server's members as a CSV.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "members",
+                    "Exports members with their verification status.",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "status",
+                        "Which members to export, defaults to all.",
+                    )
+                    .add_string_choice("verified", "verified")
+                    .add_string_choice("unverified", "unverified")
+                    .add_string_choice("all", "all"),
+                )
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Role,
+                    "role",
+                    "Only export members with this role.",
+                )),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "privacy",
+                    "Chooses which columns exports include.",
+                )
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "names",
+                    "Include usernames and display names.",
+                ))
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "link-dates",
+                    "Include when members linked their Discord accounts.",
+                )),
+            ),
+        CreateCommand::new("whois")
+            .description("Shows when a user verified and linked their accounts.")
+            .dm_permission(false)
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::User, "user", "The user to look up.")
+                    .required(true),
             ),
+        CreateCommand::new("Check verification")
+            .kind(CommandType::User)
+            .dm_permission(false)
+            .default_member_permissions(Permissions::MANAGE_ROLES),
+        CreateCommand::new("Verify this user")
+            .kind(CommandType::User)
+            .dm_permission(false)
+            .default_member_permissions(Permissions::MANAGE_ROLES),
     ]
 }
 
+/// Commands for the people running the bot, only registered in `OPERATOR_GUILD_ID`.
+fn operator_commands() -> Vec<CreateCommand> {
+    let guild_option = || {
+        CreateCommandOption::new(CommandOptionType::String, "guild", "The id of the server.")
+            .required(true)
+    };
+    vec![CreateCommand::new("operator")
+        .description("Commands for the people running the bot.")
+        .dm_permission(false)
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "registrations",
+                "Lists registered servers.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "status",
+                    "Which registrations to list, defaults to all.",
+                )
+                .add_string_choice("pending", "pending")
+                .add_string_choice("approved", "approved")
+                .add_string_choice("all", "all"),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "approve",
+                "Approves a server's registration.",
+            )
+            .add_sub_option(guild_option()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "reject",
+                "Rejects a server's registration.",
+            )
+            .add_sub_option(guild_option()),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "refresh-cache",
+            "Forgets all cached guilds and verification results.",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "leave",
+                "Makes the bot leave a server.",
+            )
+            .add_sub_option(guild_option()),
+        )]
+}
+
+/// Syncs our commands with `scope` and the operator commands with the operator guild.
+async fn register_commands(
+    http: &Http,
+    config: &Config,
+    scope: Scope,
+) -> Result<Vec<(Scope, Summary)>> {
+    let mut commands = create_commands();
+    let home = config.operator_guild_id.map(Scope::Guild);
+    // Syncing the same guild twice would delete the other set of commands.
+    if home == Some(scope) {
+        commands.extend(operator_commands());
+    }
+    let mut synced = vec![(
+        scope,
+        sync_commands(http, scope, commands)
+            .await
+            .context("Unable to create commands.")?,
+    )];
+    if let Some(home) = home.filter(|home| *home != scope) {
+        synced.push((
+            home,
+            sync_commands(http, home, operator_commands())
+                .await
+                .context("Unable to create operator commands.")?,
+        ));
+    }
+    Ok(synced)
+}
+
 struct Handler;
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
         let (guild_id, user_id) = (new_member.guild_id, new_member.user.id);
-        silent_verify(&ctx, user_id, guild_id).await;
-        TASK_LIST
-            .get()
-            .expect("OnceCell should be instantiated")
-            .send((user_id, guild_id))
+        let span = info_span!("join", id = next_id(), guild = %guild_id, user = %user_id);
+        async {
+            history::record_joined(guild_id, user_id).await;
+            if silent_verify(&ctx, user_id, guild_id).await.verified {
+                history::record_verified(guild_id, &[user_id], Method::Join).await;
+            } else {
+                mark_unverified(&ctx, &new_member).await;
+            }
+            state(&ctx)
+                .await
+                .tasks
+                .send(Task::Verify(user_id, guild_id, Method::Join))
+                .ok();
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _: Option<Member>,
+    ) {
+        state(&ctx)
+            .await
+            .tasks
+            .send(Task::Cancel(user.id, guild_id))
             .ok();
     }
 
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _: Option<Guild>) {
+        // Unavailable means there is an outage rather than us being removed.
+        if !incomplete.unavailable {
+            guild_left(&ctx, incomplete.id).await;
+        }
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        _: Option<Role>,
+    ) {
+        role_deleted(&ctx, guild_id, removed_role_id).await;
+    }
+
+    async fn guild_role_update(&self, ctx: Context, _: Option<Role>, new: Role) {
+        role_updated(&ctx, &new).await;
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
 
-        let commands = if let Some(guild_id) = env::var("TEST_GUILD_ID")
-            .ok()
-            .map(|f| GuildId::new(f.parse().expect("TEST_GUILD_ID must be an integer")))
-        {
-            GuildId::set_commands(guild_id, &ctx.http, create_commands()).await
-        } else {
-            Command::set_global_commands(&ctx, create_commands()).await
+        let state = state(&ctx).await;
+        let scope = match state.config().test_guild_id {
+            Some(guild_id) => Scope::Guild(guild_id),
+            None => Scope::Global,
         };
-
-        match commands.context("Unable to create commands.") {
-            Ok(commands) => {
-                info!("I now have the following slash commands: {commands:#?}")
+        match register_commands(&ctx.http, &state.config(), scope).await {
+            Ok(synced) => {
+                for (scope, summary) in synced {
+                    info!("Synced {scope:?} slash commands: {summary}");
+                }
             }
-            Err(e) => {
-                warn!("{e:?}")
+            Err(e) => warn!("{e:?}"),
+        }
+
+        for &guild_id in &state.config().purge_guild_ids {
+            match purge_guild_commands(&ctx.http, guild_id)
+                .await
+                .context(format!("Unable to purge commands from guild {guild_id}."))
+            {
+                Ok(summary) => info!("Purged guild {guild_id} slash commands: {summary}"),
+                Err(e) => warn!("{e:?}"),
             }
         }
 
-        let (send, recv) = unbounded_channel();
-        TASK_LIST.set(send).expect("OnceCell has not yet been set");
-        tokio::task::spawn(check_for_verify(ctx, recv));
+        // Ready is sent again after reconnecting, only the first one starts the background tasks.
+        if let Some(listener) = state.take_listener().await {
+            tokio::task::spawn(interactions::serve(ctx.clone(), listener));
+        }
+        if let Some(tasks) = state.take_tasks().await {
+            tokio::task::spawn(check_for_verify(ctx.clone(), tasks));
+            tokio::task::spawn(sync::sync_roles(ctx));
+        }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = interaction {
-            let guild = command.guild_id.unwrap();
-            let user = command.user.id;
-            if let Err(why) = dispatch_commands(&ctx, command).await {
-                warn!("Command failure in guild with id {guild} from user with id {user}: {why:?}");
+        handle_interaction(&ctx, interaction).await;
+    }
+}
+
+/// Handles an interaction, whether it came over the gateway or the interactions endpoint.
+async fn handle_interaction(ctx: &Context, interaction: Interaction) {
+    let span = match &interaction {
+        Interaction::Command(command) => info_span!(
+            "interaction",
+            id = %command.id,
+            command = %command.data.name,
+            guild = command.guild_id.map(GuildId::get),
+            user = %command.user.id,
+        ),
+        Interaction::Modal(modal) => info_span!(
+            "interaction",
+            id = %modal.id,
+            modal = %modal.data.custom_id,
+            guild = modal.guild_id.map(GuildId::get),
+            user = %modal.user.id,
+        ),
+        Interaction::Component(press) => info_span!(
+            "interaction",
+            id = %press.id,
+            component = %press.data.custom_id,
+            guild = press.guild_id.map(GuildId::get),
+            user = %press.user.id,
+        ),
+        interaction => info_span!("interaction", id = %interaction.id()),
+    };
+    async {
+        match interaction {
+            Interaction::Command(command) => {
+                let guild = command.guild_id.unwrap();
+                let user = command.user.id;
+                if let Err(why) = dispatch_commands(ctx, command).await {
+                    warn!("Command failure in guild with id {guild} from user with id {user}: {why:?}");
+                }
+            }
+            Interaction::Modal(modal) => {
+                // Nothing is waiting for the modal anymore, such as after a restart.
+                if let Some(modal) = deliver_modal(modal) {
+                    let response = CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content("This form has expired, please run the command again.")
+                            .ephemeral(true),
+                    );
+                    if let Err(why) = modal.create_response(ctx, response).await {
+                        warn!("Could not reply to expired modal: {why:?}");
+                    }
+                }
+            }
+            Interaction::Component(press) => {
+                // Nothing is waiting for the button anymore, such as after it timed out.
+                if let Some(press) = deliver_component(press) {
+                    let response = CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new()
+                            .content("This has expired, please run the command again.")
+                            .components(vec![]),
+                    );
+                    if let Err(why) = press.create_response(ctx, response).await {
+                        warn!("Could not reply to expired component: {why:?}");
+                    }
+                }
             }
+            _ => {}
         }
     }
+    .instrument(span)
+    .await
 }
 
 async fn dispatch_commands(ctx: &Context, command: CommandInteraction) -> Result<()> {
@@ -109,67 +443,90 @@ async fn dispatch_commands(ctx: &Context, command: CommandInteraction) -> Result
             .await
             .context("Failed to run setup command"),
         "setup-modal" => Ok(()),
+        "diagnose" => diagnose(ctx, command)
+            .await
+            .context("Failed to run diagnose command."),
+        "lockdown" => lockdown(ctx, command)
+            .await
+            .context("Failed to run lockdown command."),
+        "audit" => audit(ctx, command)
+            .await
+            .context("Failed to run audit command."),
+        "stats" => stats(ctx, command)
+            .await
+            .context("Failed to run stats command."),
+        "export" => export(ctx, command)
+            .await
+            .context("Failed to run export command."),
+        "whois" => whois(ctx, command)
+            .await
+            .context("Failed to run whois command."),
+        "operator" => operator(ctx, command)
+            .await
+            .context("Failed to run operator command."),
+        "Check verification" => check_verification(ctx, command)
+            .await
+            .context("Failed to run check verification command."),
+        "Verify this user" => verify_user(ctx, command)
+            .await
+            .context("Failed to run verify this user command."),
         command => Err(anyhow!("{command} command is not implemented.")),
     }
 }
 
-static TASK_LIST: OnceCell<UnboundedSender<(UserId, GuildId)>> = OnceCell::new();
-
-async fn check_for_verify(ctx: Context, mut rec: UnboundedReceiver<(UserId, GuildId)>) -> ! {
-    let ctx = &ctx;
-    let mut tries = HashMap::new();
-    let mut task_list_a = FuturesUnordered::new();
-    let mut task_list_b = FuturesUnordered::new();
-    const TRIES: i32 = 60;
-    const TIMEOUT: Duration = Duration::from_secs(3);
-    loop {
-        while let Ok(new_task) = rec.try_recv() {
-            if let Some(0) | None = tries.get(&new_task.0) {
-                // Only add a task if one doesn't already exist.
-                task_list_a.push(silent_verify(ctx, new_task.0, new_task.1))
-            }
-            tries.insert(new_task.0, TRIES);
-        }
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // Kept so reloads can tell what .env set apart from what was set outside it.
+    let environment = env::vars().collect();
+    dotenvy::dotenv().ok();
+    let log_config = reload::log_config(env::var("LOG_CONFIG").ok().as_deref())?;
+    let logging = log4rs::init_config(log_config).context("Unable to start logging")?;
+    let exporter = telemetry::init()?;
+    let config = Config::from_env().context("Invalid configuration")?;
 
-        while let Some(task) = task_list_a.next().await {
-            let new_tries = tries.get_mut(&task.user_id).map(|t| {
-                *t -= 1;
-                *t
-            });
-            match new_tries {
-                Some(0) | None => {
-                    tries.remove(&task.user_id);
-                }
-                Some(_) => {
-                    if !task.verified {
-                        task_list_b.push(silent_verify(ctx, task.user_id, task.guild_id));
-                    }
-                }
-            }
+    let result = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(config, logging, environment).await,
+        Command::RegisterCommands { guild } => {
+            async { cli::register_commands(&cli::discord(&config).await?, &config, guild).await }
+                .await
         }
-        mem::swap(&mut task_list_a, &mut task_list_b);
-        // b will now drained and a will contain the scheduled futures
-        tokio::time::sleep(TIMEOUT).await;
+        Command::PurgeCommands { guilds } => {
+            async { cli::purge_commands(&cli::discord(&config).await?, &config, guilds).await }
+                .await
+        }
+        Command::CheckUser { user, guild } => cli::check_user(&config, user, guild).await,
+        Command::ConfigCheck => cli::config_check(&config).await,
+    };
+    if let Some(exporter) = exporter {
+        exporter.shutdown().ok();
     }
+    result
 }
 
-#[tokio::main]
-async fn main() {
-    let config_str = include_str!("./../log4rs.yml");
-    let config = serde_yaml::from_str(config_str).unwrap();
-    log4rs::init_raw_config(config).unwrap();
-
-    dotenv::dotenv().ok();
-    let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN environment var has not been set");
+async fn run(config: Config, logging: log4rs::Handle, environment: Vars) -> Result<()> {
+    store::init(config.data_dir.clone());
+    let api = ApiClient::new(&config).context("Unable to create the API client")?;
+    let mut state = AppState::new(config.clone(), api);
+    if let Some(addr) = config.interactions_addr {
+        let listener = TcpListener::bind(addr)
+            .await
+            .context(format!("Unable to listen for interactions on {addr}"))?;
+        state = state.with_listener(listener);
+    }
+    let state = Arc::new(state);
+    tokio::task::spawn(reload::watch(state.clone(), logging, environment));
 
-    let mut client = Client::builder(token, GatewayIntents::GUILD_MEMBERS)
+    let mut client = Client::builder(&config.discord_token, INTENTS)
         .event_handler(Handler)
+        .type_map_insert::<AppState>(state)
         .await
-        .expect("Error creating client");
+        .context("Error creating client")?;
 
     info!("Client successfully created!");
 
     if let Err(why) = client.start().await {
         warn!("Client error: {:?}", why);
     }
+    Ok(())
 }
@@ -0,0 +1,173 @@
+//! Keeps the slash commands registered with Discord in sync with `create_commands`, only sending
+//! the commands that have actually changed.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{Context as ContextTrait, Result};
+use serde_json::{json, Value};
+use serenity::all::{Command, CommandId, CreateCommand};
+use serenity::http::Http;
+use serenity::model::id::GuildId;
+
+/// Where a set of commands is registered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Guild(GuildId),
+}
+
+/// What a sync changed.
+#[derive(Default, Debug)]
+pub struct Summary {
+    pub created: Vec<String>,
+    pub edited: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created {:?}, edited {:?}, deleted {:?}, {} unchanged",
+            self.created, self.edited, self.deleted, self.unchanged
+        )
+    }
+}
+
+impl Scope {
+    async fn get(self, http: &Http) -> serenity::Result<Vec<Command>> {
+        match self {
+            Scope::Global => Command::get_global_commands(http).await,
+            Scope::Guild(guild_id) => guild_id.get_commands(http).await,
+        }
+    }
+
+    async fn create(self, http: &Http, command: CreateCommand) -> serenity::Result<Command> {
+        match self {
+            Scope::Global => Command::create_global_command(http, command).await,
+            Scope::Guild(guild_id) => guild_id.create_command(http, command).await,
+        }
+    }
+
+    async fn edit(
+        self,
+        http: &Http,
+        id: CommandId,
+        command: CreateCommand,
+    ) -> serenity::Result<Command> {
+        match self {
+            Scope::Global => Command::edit_global_command(http, id, command).await,
+            Scope::Guild(guild_id) => guild_id.edit_command(http, id, command).await,
+        }
+    }
+
+    async fn delete(self, http: &Http, id: CommandId) -> serenity::Result<()> {
+        match self {
+            Scope::Global => Command::delete_global_command(http, id).await,
+            Scope::Guild(guild_id) => guild_id.delete_command(http, id).await,
+        }
+    }
+}
+
+/// Removes anything out of our control from an option tree, such as defaults Discord fills in.
+fn strip(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(k, _)| !k.ends_with("localizations") && !k.ends_with("localized"))
+                .map(|(k, v)| (k, strip(v)))
+                .filter(|(_, v)| match v {
+                    Value::Null | Value::Bool(false) => false,
+                    Value::Array(a) => !a.is_empty(),
+                    Value::Object(o) => !o.is_empty(),
+                    _ => true,
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(strip).collect()),
+        value => value,
+    }
+}
+
+/// Reduces a command to the fields we set, filling in Discord's defaults so a freshly built
+/// command and one fetched from Discord compare equal.
+pub fn canonical(command: Value, scope: Scope) -> Value {
+    let field = |key: &str, default: Value| {
+        command
+            .get(key)
+            .filter(|v| !v.is_null())
+            .cloned()
+            .unwrap_or(default)
+    };
+    json!({
+        "name": field("name", Value::Null),
+        "type": field("type", json!(1)),
+        "description": field("description", json!("")),
+        "options": strip(field("options", json!([]))),
+        "default_member_permissions": field("default_member_permissions", Value::Null),
+        // Only global commands can be used in DMs, Discord leaves it out for guild commands.
+        "dm_permission": match scope {
+            Scope::Global => field("dm_permission", json!(true)),
+            Scope::Guild(_) => Value::Null,
+        },
+        "nsfw": field("nsfw", json!(false)),
+    })
+}
+
+/// Creates, edits and deletes commands so the ones registered in `scope` match `commands`.
+pub async fn sync_commands(
+    http: &Http,
+    scope: Scope,
+    commands: Vec<CreateCommand>,
+) -> Result<Summary> {
+    let mut existing = scope
+        .get(http)
+        .await
+        .context(concat!(file!(), ":", line!()))?
+        .into_iter()
+        .map(|c| {
+            Ok((
+                c.name.clone(),
+                (c.id, canonical(serde_json::to_value(&c)?, scope)),
+            ))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let mut summary = Summary::default();
+    for command in commands {
+        let wanted = canonical(serde_json::to_value(&command)?, scope);
+        let name = wanted["name"].as_str().unwrap_or_default().to_string();
+        match existing.remove(&name) {
+            Some((_, current)) if current == wanted => summary.unchanged += 1,
+            Some((id, _)) => {
+                scope
+                    .edit(http, id, command)
+                    .await
+                    .context(format!("Could not edit the {name} command."))?;
+                summary.edited.push(name);
+            }
+            None => {
+                scope
+                    .create(http, command)
+                    .await
+                    .context(format!("Could not create the {name} command."))?;
+                summary.created.push(name);
+            }
+        }
+    }
+    for (name, (id, _)) in existing {
+        scope
+            .delete(http, id)
+            .await
+            .context(format!("Could not delete the {name} command."))?;
+        summary.deleted.push(name);
+    }
+    Ok(summary)
+}
+
+/// Deletes every command registered in a guild, such as those left behind by `TEST_GUILD_ID`.
+pub async fn purge_guild_commands(http: &Http, guild_id: GuildId) -> Result<Summary> {
+    sync_commands(http, Scope::Guild(guild_id), vec![]).await
+}
@@ -0,0 +1,172 @@
+//! Reloads the configuration on SIGHUP or when `.env` or `LOG_CONFIG` change, so secrets such as
+//! `API_KEY` can be rotated and log levels changed without dropping the gateway connection.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context as ContextTrait, Result};
+use cached::Cached;
+use log4rs::config::{Deserializers, RawConfig};
+use log4rs::Handle;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+use crate::commands::{api, ApiClient};
+use crate::config::{Config, Vars};
+use crate::state::AppState;
+
+/// Used when `LOG_CONFIG` isn't set.
+const DEFAULT_LOG_CONFIG: &str = include_str!("./../log4rs.yml");
+
+/// How often to check whether the watched files have changed.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Builds the log settings from the `LOG_CONFIG` file at `path`, or the defaults built into the
+/// bot.
+pub fn log_config(path: Option<&str>) -> Result<log4rs::Config> {
+    let raw = match path {
+        Some(path) => fs::read_to_string(path).context(format!("Unable to read {path}"))?,
+        None => DEFAULT_LOG_CONFIG.to_string(),
+    };
+    let raw: RawConfig = serde_yaml::from_str(&raw).context("Invalid log config")?;
+    let (appenders, errors) = raw.appenders_lossy(&Deserializers::default());
+    if !errors.is_empty() {
+        bail!("Invalid log appenders: {errors:?}");
+    }
+    log4rs::Config::builder()
+        .appenders(appenders)
+        .loggers(raw.loggers())
+        .build(raw.root())
+        .context("Invalid log config")
+}
+
+/// The files that trigger a reload when they change.
+fn watched_files(vars: &Vars) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(".env")];
+    files.extend(vars.get("LOG_CONFIG").map(PathBuf::from));
+    files
+}
+
+/// Runs file IO off the async runtime's threads.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .context(concat!(file!(), ":", line!()))
+}
+
+async fn modified_times(files: Vec<PathBuf>) -> Vec<Option<SystemTime>> {
+    blocking(move || {
+        files
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Reads the variables `.env` sets, a missing file setting none.
+fn env_file() -> Result<Vec<(String, String)>> {
+    match dotenvy::from_path_iter(".env") {
+        Ok(vars) => vars
+            .collect::<Result<_, _>>()
+            .context("Unable to parse .env"),
+        Err(e) if e.not_found() => Ok(vec![]),
+        Err(e) => Err(e).context("Unable to read .env"),
+    }
+}
+
+/// The variables the bot would start with now: `.env` overlaid with `environment`, the variables
+/// set outside it, which win like they do at startup.
+async fn vars(environment: &Vars) -> Result<Vars> {
+    let mut vars: Vars = blocking(env_file).await??.into_iter().collect();
+    vars.extend(environment.clone());
+    Ok(vars)
+}
+
+/// Reloads everything whenever the process gets SIGHUP or a watched file changes. `environment`
+/// is the process environment from before `.env` was loaded.
+///
+/// The new configuration is built from the variables directly, setting them would override those
+/// set outside `.env` and race with anything reading the environment.
+pub async fn watch(state: Arc<AppState>, logging: Handle, environment: Vars) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Unable to listen for SIGHUP, the configuration won't be reloaded. {e:?}");
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    let mut files = watched_files(&env::vars().collect());
+    let mut modified = modified_times(files.clone()).await;
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("Received SIGHUP, reloading the configuration."),
+            _ = interval.tick() => {
+                let now = modified_times(files.clone()).await;
+                if now == modified {
+                    continue;
+                }
+                info!("Configuration files changed, reloading the configuration.");
+            }
+        }
+        match vars(&environment).await {
+            Ok(vars) => {
+                reload(&state, &logging, &vars).await;
+                // LOG_CONFIG may have moved.
+                files = watched_files(&vars);
+            }
+            Err(e) => warn!("Keeping the current configuration as .env can't be read. {e:?}"),
+        }
+        modified = modified_times(files.clone()).await;
+    }
+}
+
+async fn reload(state: &AppState, logging: &Handle, vars: &Vars) {
+    let path = vars.get("LOG_CONFIG").cloned();
+    match blocking(move || log_config(path.as_deref()))
+        .await
+        .and_then(|config| config)
+    {
+        Ok(config) => logging.set_config(config),
+        Err(e) => warn!("Keeping the current log settings. {e:?}"),
+    }
+
+    let config = match Config::from_vars(vars) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Keeping the current configuration as the new one is invalid. {e:?}");
+            return;
+        }
+    };
+    let api = match ApiClient::new(&config) {
+        Ok(api) => api,
+        Err(e) => {
+            warn!("Keeping the current configuration as the API client can't be built. {e:?}");
+            return;
+        }
+    };
+
+    let old = state.config();
+    if config.discord_token != old.discord_token {
+        warn!("DISCORD_TOKEN changed, restart the bot to use the new token.");
+    }
+    if config.interactions_addr != old.interactions_addr {
+        warn!("INTERACTIONS_ADDR changed, restart the bot to listen on the new address.");
+    }
+    if config.data_dir != old.data_dir {
+        warn!("DATA_DIR changed, restart the bot to keep its files there.");
+    }
+    // Anything cached came from the old API.
+    if config.api_url != old.api_url {
+        api::GET_GUILD.lock().await.cache_clear();
+        api::IS_VERIFIED.lock().await.cache_clear();
+    }
+    state.replace_config(config, api);
+    info!("Reloaded the configuration.");
+}
@@ -0,0 +1,175 @@
+//! Keeps retrying verification for members who weren't verified when they joined or ran /verify,
+//! as they are usually still on the website linking their accounts.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use serenity::client::Context;
+use serenity::model::prelude::{GuildId, UserId};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::Instant;
+use tokio_util::time::{delay_queue, DelayQueue};
+use tracing::{info, info_span, Instrument};
+
+use crate::commands::{silent_verify, RetryPolicy};
+use crate::history::{self, Method};
+use crate::state::state;
+use crate::telemetry::next_id;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Task {
+    /// Keep trying to verify a user in a guild, recording how they verified once they do.
+    Verify(UserId, GuildId, Method),
+    /// Stop trying to verify a user in a guild, such as when they leave it.
+    Cancel(UserId, GuildId),
+    /// Stop trying to verify anyone in a guild.
+    CancelGuild(GuildId),
+}
+
+/// How long to wait before the next attempt, slowing down the longer someone takes to verify.
+fn next_delay(elapsed: Duration) -> Duration {
+    if elapsed < Duration::from_secs(2 * 60) {
+        Duration::from_secs(3)
+    } else if elapsed < Duration::from_secs(15 * 60) {
+        Duration::from_secs(30)
+    } else {
+        Duration::from_secs(5 * 60)
+    }
+}
+
+/// Retries held back by a pause are spread over this long after it ends, so they don't all hit
+/// the API at once.
+const PAUSE_JITTER: Duration = Duration::from_secs(10);
+
+/// How long after a pause to retry a user, the same every time so it doesn't need randomness.
+fn jitter(key: (UserId, GuildId)) -> Duration {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    Duration::from_millis(hasher.finish() % PAUSE_JITTER.as_millis() as u64)
+}
+
+struct Entry {
+    /// When we started trying to verify the user, retries stop once this is past the horizon.
+    started: Instant,
+    /// The entry's position in the delay queue, `None` while an attempt is running.
+    key: Option<delay_queue::Key>,
+    method: Method,
+    /// Distinguishes the entry from earlier ones for the same user, whose attempts may still be
+    /// running after being cancelled.
+    generation: u64,
+}
+
+pub async fn check_for_verify(ctx: Context, mut tasks: UnboundedReceiver<Task>) {
+    let ctx = &ctx;
+    let state = state(ctx).await;
+    let mut queue = DelayQueue::new();
+    let mut entries: HashMap<(UserId, GuildId), Entry> = HashMap::new();
+    let mut running = FuturesUnordered::new();
+    // Set when the API asks us to back off, nobody is retried until then.
+    let mut paused_until = Instant::now();
+    let mut generations = 0..;
+
+    loop {
+        tokio::select! {
+            Some(task) = tasks.recv() => match task {
+                Task::Verify(user_id, guild_id, method) => {
+                    let key = (user_id, guild_id);
+                    match entries.get_mut(&key) {
+                        // Already being retried, just give them longer.
+                        Some(entry) => entry.started = Instant::now(),
+                        None => {
+                            // Whoever sent the task has just tried, so wait before the first retry.
+                            let queue_key = queue.insert(key, next_delay(Duration::ZERO));
+                            let entry = Entry {
+                                started: Instant::now(),
+                                key: Some(queue_key),
+                                method,
+                                generation: generations.next().unwrap(),
+                            };
+                            entries.insert(key, entry);
+                        }
+                    }
+                }
+                Task::Cancel(user_id, guild_id) => {
+                    let entry = entries.remove(&(user_id, guild_id));
+                    if let Some(Entry { key: Some(key), .. }) = entry {
+                        queue.remove(&key);
+                    }
+                }
+                Task::CancelGuild(guild_id) => {
+                    // Running attempts are dropped when they finish as they have no entry.
+                    entries.retain(|(_, g), entry| {
+                        if *g != guild_id {
+                            return true;
+                        }
+                        if let Some(key) = entry.key.take() {
+                            queue.remove(&key);
+                        }
+                        false
+                    });
+                }
+            },
+            Some(expired) = queue.next() => {
+                let key = expired.into_inner();
+                let Some(entry) = entries.get_mut(&key) else {
+                    continue;
+                };
+                if paused_until > Instant::now() {
+                    entry.key = Some(queue.insert_at(key, paused_until + jitter(key)));
+                } else {
+                    entry.key = None;
+                    let (user_id, guild_id) = key;
+                    let generation = entry.generation;
+                    let span = info_span!("retry", id = next_id(), guild = %guild_id, user = %user_id);
+                    running.push(
+                        silent_verify(ctx, user_id, guild_id)
+                            .instrument(span)
+                            .map(move |result| (generation, result)),
+                    );
+                }
+            },
+            Some((generation, result)) = running.next() => {
+                let key = (result.user_id, result.guild_id);
+                let Some(entry) = entries
+                    .get_mut(&key)
+                    .filter(|entry| entry.generation == generation)
+                else {
+                    continue;
+                };
+                if result.verified {
+                    history::record_verified(key.1, &[key.0], entry.method).await;
+                }
+                let elapsed = entry.started.elapsed();
+                let horizon = state.config().retry_horizon;
+                let delay = match result.retry {
+                    _ if result.verified => None,
+                    RetryPolicy::Never => None,
+                    _ if elapsed >= horizon => {
+                        info!(
+                            "Gave up verifying user {} in guild {} after {elapsed:?}.",
+                            key.0, key.1
+                        );
+                        None
+                    }
+                    RetryPolicy::Backoff(backoff) => {
+                        // The API is struggling so slow down for everyone.
+                        paused_until = paused_until.max(Instant::now() + backoff);
+                        Some(next_delay(elapsed).max(backoff))
+                    }
+                    RetryPolicy::Retry => Some(next_delay(elapsed)),
+                };
+                match delay {
+                    Some(delay) => entry.key = Some(queue.insert(key, delay)),
+                    None => {
+                        entries.remove(&key);
+                    }
+                }
+            },
+            else => return,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! State shared with every event handler through serenity's `TypeMap`.
+
+use std::sync::{Arc, RwLock};
+
+use serenity::client::Context;
+use serenity::prelude::TypeMapKey;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+
+use crate::commands::ApiClient;
+use crate::config::Config;
+use crate::scheduler::Task;
+
+pub struct AppState {
+    /// Replaced when the configuration is reloaded, see `reload`.
+    config: RwLock<Arc<Config>>,
+    api: RwLock<ApiClient>,
+    /// Sends tasks to the `check_for_verify` loop.
+    pub tasks: UnboundedSender<Task>,
+    /// Taken by the first `ready` event to start `check_for_verify`, so reconnects don't start
+    /// another one.
+    pending_tasks: Mutex<Option<UnboundedReceiver<Task>>>,
+    /// Bound before connecting when `INTERACTIONS_ADDR` is set and taken by the first `ready`
+    /// event to serve interactions.
+    listener: Mutex<Option<TcpListener>>,
+}
+
+impl TypeMapKey for AppState {
+    type Value = Arc<AppState>;
+}
+
+impl AppState {
+    pub fn new(config: Config, api: ApiClient) -> AppState {
+        let (tasks, pending_tasks) = unbounded_channel();
+        AppState {
+            config: RwLock::new(Arc::new(config)),
+            api: RwLock::new(api),
+            tasks,
+            pending_tasks: Mutex::new(Some(pending_tasks)),
+            listener: Mutex::new(None),
+        }
+    }
+
+    pub fn with_listener(self, listener: TcpListener) -> AppState {
+        AppState {
+            listener: Mutex::new(Some(listener)),
+            ..self
+        }
+    }
+
+    /// Gets the current configuration.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Gets a client for the verify API using the current configuration.
+    pub fn api(&self) -> ApiClient {
+        self.api.read().unwrap().clone()
+    }
+
+    /// Swaps in a new configuration, along with an API client built from it.
+    pub fn replace_config(&self, config: Config, api: ApiClient) {
+        *self.config.write().unwrap() = Arc::new(config);
+        *self.api.write().unwrap() = api;
+    }
+
+    /// Gets the receiving end of the task queue, only returning it the first time it's called.
+    pub async fn take_tasks(&self) -> Option<UnboundedReceiver<Task>> {
+        self.pending_tasks.lock().await.take()
+    }
+
+    /// Gets the interactions listener, only returning it the first time it's called.
+    pub async fn take_listener(&self) -> Option<TcpListener> {
+        self.listener.lock().await.take()
+    }
+}
+
+/// Gets the state inserted into the client's data when it was built.
+pub async fn state(ctx: &Context) -> Arc<AppState> {
+    ctx.data
+        .read()
+        .await
+        .get::<AppState>()
+        .cloned()
+        .expect("AppState is inserted before the client starts")
+}
@@ -0,0 +1,91 @@
+//! Small JSON files kept in `DATA_DIR` for anything the verify API doesn't store for us.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::{fs, io};
+
+use anyhow::{Context as ContextTrait, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+/// Held while reading and writing files so concurrent updates don't clobber each other.
+static LOCK: Mutex<()> = Mutex::const_new(());
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory files are kept in, changing it needs a restart as nothing moves the files.
+pub fn init(dir: PathBuf) {
+    DIR.set(dir).ok();
+}
+
+fn path(name: &str) -> PathBuf {
+    DIR.get().expect("store::init wasn't called").join(name)
+}
+
+/// Runs file IO off the async runtime's threads.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .context(concat!(file!(), ":", line!()))?
+}
+
+async fn read<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let (path, file) = (path(name), name.to_string());
+    let bytes = blocking(move || match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("Could not read {file}")),
+    })
+    .await?;
+    match bytes {
+        Some(bytes) => serde_json::from_slice(&bytes).context(format!("Could not parse {name}")),
+        None => Ok(T::default()),
+    }
+}
+
+async fn write<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    let bytes = serde_json::to_vec(value)?;
+    let (path, file) = (path(name), name.to_string());
+    blocking(move || {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(concat!(file!(), ":", line!()))?;
+        }
+        // Write to a temporary file first so a crash can't leave a half written file behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes).context(format!("Could not write {file}"))?;
+        fs::rename(&tmp, &path).context(format!("Could not write {file}"))
+    })
+    .await
+}
+
+/// Loads a file, returning the default value if it doesn't exist yet.
+pub async fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let _lock = LOCK.lock().await;
+    read(name).await
+}
+
+/// Loads a file, lets `f` modify it and then saves it again.
+pub async fn update<T, R>(name: &str, f: impl FnOnce(&mut T) -> R) -> Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let _lock = LOCK.lock().await;
+    let mut value = read(name).await?;
+    let result = f(&mut value);
+    write(name, &value).await?;
+    Ok(result)
+}
+
+/// Deletes a file, doing nothing if it doesn't exist.
+pub async fn remove(name: &str) -> Result<()> {
+    let _lock = LOCK.lock().await;
+    let (path, file) = (path(name), name.to_string());
+    blocking(move || match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).context(format!("Could not delete {file}"))
+        }
+        _ => Ok(()),
+    })
+    .await
+}
@@ -0,0 +1,164 @@
+//! Periodically brings the roles of every member in every guild up to date with the verify API,
+//! as paid memberships and graduations happen without anyone running a command.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use anyhow::{Context as ContextTrait, Result};
+use serenity::client::Context;
+use serenity::futures::StreamExt;
+use serenity::model::prelude::{GuildId, UserId};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::commands::api::{self, ApiError, Status};
+use crate::commands::{graduate, set_member_role, ApiClient};
+use crate::state::state;
+use crate::telemetry::next_id;
+
+/// How many members to look up with each batch request.
+const BATCH_SIZE: usize = 100;
+
+/// How often to check whether syncing has been turned back on while `SYNC_INTERVAL` is 0.
+const DISABLED_POLL: Duration = Duration::from_secs(60);
+
+/// Syncs every guild once per `SYNC_INTERVAL`, which is re-read each time so reloading the
+/// configuration can change it.
+pub async fn sync_roles(ctx: Context) {
+    loop {
+        let interval = state(&ctx).await.config().sync_interval;
+        if interval.is_zero() {
+            tokio::time::sleep(DISABLED_POLL).await;
+            continue;
+        }
+        tokio::time::sleep(interval).await;
+
+        for guild_id in ctx.cache.guilds() {
+            let span = info_span!("sync", id = next_id(), guild = %guild_id);
+            if let Err(e) = sync_guild(&ctx, guild_id).instrument(span).await {
+                warn!("Could not sync roles in guild {guild_id}. {e:?}");
+            }
+        }
+    }
+}
+
+/// Brings the member and alumni roles of everyone in a guild up to date.
+pub async fn sync_guild(ctx: &Context, guild_id: GuildId) -> Result<()> {
+    let api = state(ctx).await.api();
+    let guild = match api::get_guild(&api, guild_id).await {
+        Ok(guild) => guild,
+        // Nothing to keep in sync until the guild is set up.
+        Err(ApiError::GuildNotRegistered) => return Ok(()),
+        Err(e) => return Err(e).context(concat!(file!(), ":", line!())),
+    };
+    if guild.member_role_id.is_none() && guild.alumni_role_id.is_none() {
+        return Ok(());
+    }
+
+    let mut members = vec![];
+    let mut stream = guild_id.members_iter(ctx).boxed();
+    while let Some(member) = stream.next().await {
+        let member = member.context(concat!(file!(), ":", line!()))?;
+        if !member.user.bot {
+            members.push(member);
+        }
+    }
+    let verified = members
+        .iter()
+        .filter(|m| m.roles.contains(&guild.role_id))
+        .map(|m| m.user.id)
+        .collect::<Vec<_>>();
+
+    let mut graduated = HashSet::new();
+    if guild.alumni_role_id.is_some() {
+        for user_id in graduates(&api, guild_id, &verified).await {
+            match graduate(ctx, guild_id, user_id, guild).await {
+                Ok(()) => {
+                    graduated.insert(user_id);
+                }
+                Err(e) => warn!("Could not move a graduate to the alumni role. {e:?}"),
+            }
+        }
+    }
+    if !graduated.is_empty() {
+        info!("Moved {} graduates to the alumni role.", graduated.len());
+    }
+
+    let Some(member_role) = guild.member_role_id else {
+        return Ok(());
+    };
+    let held = members
+        .iter()
+        // Unverified members without the role can be skipped, the API has nothing on them. Those
+        // who just graduated are too, as `roles` is from before their roles were swapped.
+        .filter(|m| !graduated.contains(&m.user.id))
+        .filter(|m| m.roles.contains(&member_role) || m.roles.contains(&guild.role_id))
+        .map(|m| (m.user.id, m.roles.contains(&member_role)))
+        .collect::<HashMap<_, _>>();
+    let user_ids = held.keys().copied().collect::<Vec<_>>();
+    for (user_id, member) in memberships(&api, guild_id, &user_ids).await {
+        let held = Some(held[&user_id]);
+        if let Err(e) = set_member_role(ctx, guild_id, user_id, member_role, member, held).await {
+            warn!("Could not update member role. {e:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Which of `user_ids` the API says have graduated, checked in batches when the API supports it.
+async fn graduates(api: &ApiClient, guild_id: GuildId, user_ids: &[UserId]) -> Vec<UserId> {
+    let mut graduates = vec![];
+    let mut batch_supported = true;
+    for chunk in user_ids.chunks(BATCH_SIZE) {
+        if batch_supported {
+            match api::batch_graduates(api, chunk, guild_id).await {
+                Ok(Some(batch)) => {
+                    graduates.extend(chunk.iter().filter(|u| batch.contains(u)));
+                    continue;
+                }
+                Ok(None) => batch_supported = false,
+                Err(e) => {
+                    warn!("Could not look up graduates in bulk, checking individually. {e:?}")
+                }
+            }
+        }
+        for &user_id in chunk {
+            match api::get_verified(api, user_id, guild_id).await {
+                Ok(verified) if verified.status == Status::Graduate => graduates.push(user_id),
+                Ok(_) | Err(ApiError::NotVerified) => {}
+                Err(e) => warn!("Could not check whether a member has graduated. {e:?}"),
+            }
+        }
+    }
+    graduates
+}
+
+/// Whether each of `user_ids` has paid for membership, checked in batches when the API supports
+/// it. Anyone who couldn't be checked is left out so their role is left alone.
+async fn memberships(
+    api: &ApiClient,
+    guild_id: GuildId,
+    user_ids: &[UserId],
+) -> Vec<(UserId, bool)> {
+    let mut memberships = vec![];
+    let mut batch_supported = true;
+    for chunk in user_ids.chunks(BATCH_SIZE) {
+        if batch_supported {
+            match api::batch_members(api, chunk, guild_id).await {
+                Ok(Some(batch)) => {
+                    memberships.extend(chunk.iter().map(|&u| (u, batch.contains(&u))));
+                    continue;
+                }
+                Ok(None) => batch_supported = false,
+                Err(e) => warn!("Could not look up members in bulk, checking individually. {e:?}"),
+            }
+        }
+        for &user_id in chunk {
+            match api::is_member(api, user_id, guild_id).await {
+                Ok(member) => memberships.push((user_id, member)),
+                Err(ApiError::NotVerified) => memberships.push((user_id, false)),
+                Err(e) => warn!("Could not check whether a member has paid. {e:?}"),
+            }
+        }
+    }
+    memberships
+}
@@ -0,0 +1,174 @@
+//! Spans tying each interaction, join and background retry to the API and Discord requests it
+//! makes. Events are still written by log4rs, prefixed with the fields of the spans they happened
+//! in, and spans are exported over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+
+use std::env;
+use std::fmt::{Debug, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context as ContextTrait, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Gets an id for a span that doesn't have one of its own, such as a join event.
+pub fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Installs the global subscriber, returning the OTLP exporter if there is one so it can be
+/// flushed before exiting.
+pub fn init() -> Result<Option<SdkTracerProvider>> {
+    let exporting = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|key| env::var(key).is_ok_and(|v| !v.trim().is_empty()));
+    let provider = if exporting {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .build()
+            .context("Unable to create the OTLP exporter")?;
+        Some(
+            SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(Resource::builder().with_service_name("verify-bot").build())
+                .build(),
+        )
+    } else {
+        None
+    };
+
+    // Serenity has spans for its gateway internals too, only its requests are worth exporting.
+    let exported = Targets::new()
+        .with_target("verify_bot", Level::INFO)
+        .with_target("serenity::http", Level::INFO);
+    let otlp = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("verify-bot"))
+            .with_filter(exported)
+    });
+    let subscriber = Registry::default().with(LogBridge).with(otlp);
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Unable to set the tracing subscriber")?;
+    Ok(provider)
+}
+
+/// Passes tracing events on to log4rs.
+pub struct LogBridge;
+
+/// A span's fields formatted as `key=value` pairs, kept in its extensions.
+struct SpanFields(String);
+
+struct FieldWriter<'a> {
+    fields: &'a mut String,
+    message: Option<String>,
+}
+
+impl<'a> FieldWriter<'a> {
+    fn new(fields: &'a mut String) -> FieldWriter<'a> {
+        FieldWriter {
+            fields,
+            message: None,
+        }
+    }
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            write!(self.fields, " {}={value}", field.name()).ok();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{value:?}"));
+        } else {
+            write!(self.fields, " {}={value:?}", field.name()).ok();
+        }
+    }
+}
+
+fn log_level(level: &Level) -> log::Level {
+    match *level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl<S> Layer<S> for LogBridge
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = String::new();
+        attrs.record(&mut FieldWriter::new(&mut fields));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut FieldWriter::new(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let log_metadata = log::Metadata::builder()
+            .level(log_level(metadata.level()))
+            .target(metadata.target())
+            .build();
+        let logger = log::logger();
+        if !logger.enabled(&log_metadata) {
+            return;
+        }
+
+        let mut line = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions.get::<SpanFields>().map_or("", |f| f.0.trim());
+                write!(line, "{}{{{fields}}}: ", span.name()).ok();
+            }
+        }
+        let mut fields = String::new();
+        let mut writer = FieldWriter::new(&mut fields);
+        event.record(&mut writer);
+        line.push_str(&writer.message.unwrap_or_default());
+        line.push_str(&fields);
+
+        logger.log(
+            &log::Record::builder()
+                .metadata(log_metadata)
+                .args(format_args!("{line}"))
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use cached::Cached;
+use serde_json::{json, Value};
+use serenity::all::{GatewayIntents, GuildId};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{unique_id, Ids, Replay};
+use crate::commands::api;
+use crate::state::state;
+
+/// Gives the guild a system channel for alerts to be posted in, returning its id.
+async fn system_channel(replay: &Replay) -> u64 {
+    let channel = unique_id();
+    let mut guild = replay.fixture("guild");
+    guild["system_channel_id"] = json!(channel.to_string());
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(guild))
+        .mount(&replay.discord)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/api/v10/channels/{channel}/messages")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(replay.fixture("message")))
+        .mount(&replay.discord)
+        .await;
+    channel
+}
+
+/// Everything posted in the system channel.
+async fn alerts(replay: &Replay, channel: u64) -> Vec<Value> {
+    replay
+        .discord_requests()
+        .await
+        .into_iter()
+        .filter(|r| r.url.path() == format!("/api/v10/channels/{channel}/messages"))
+        .map(|r| serde_json::from_slice::<Value>(&r.body).unwrap()["content"].clone())
+        .collect()
+}
+
+async fn is_cached(replay: &Replay) -> bool {
+    api::GET_GUILD
+        .lock()
+        .await
+        .cache_get(&GuildId::new(replay.ids.guild))
+        .is_some()
+}
+
+#[tokio::test]
+async fn identifies_with_the_intents_cleanup_needs() {
+    let replay = Replay::start(Ids::new()).await;
+
+    assert!(replay
+        .intents
+        .contains(GatewayIntents::GUILDS | GatewayIntents::GUILD_MEMBERS));
+}
+
+#[tokio::test]
+async fn alerts_admins_when_the_verified_role_is_deleted() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    let channel = system_channel(&replay).await;
+
+    replay
+        .event(
+            "GUILD_ROLE_DELETE",
+            json!({
+                "guild_id": replay.ids.guild.to_string(),
+                "role_id": replay.ids.role.to_string(),
+            }),
+        )
+        .await;
+
+    assert_eq!(
+        alerts(&replay, channel).await,
+        ["The verified role has been deleted so I can no longer verify anyone, please run /setup again with a new verified role."]
+    );
+    assert!(!is_cached(&replay).await);
+}
+
+#[tokio::test]
+async fn alerts_admins_when_the_unverified_role_is_deleted() {
+    let mut replay = Replay::start(Ids::new()).await;
+    let unverified_role = unique_id();
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "roleId": replay.ids.role.to_string(),
+            "unverifiedRoleId": unverified_role.to_string(),
+            "approved": true,
+        })))
+        .mount(&replay.api)
+        .await;
+    let channel = system_channel(&replay).await;
+
+    replay
+        .event(
+            "GUILD_ROLE_DELETE",
+            json!({
+                "guild_id": replay.ids.guild.to_string(),
+                "role_id": unverified_role.to_string(),
+            }),
+        )
+        .await;
+
+    assert_eq!(
+        alerts(&replay, channel).await,
+        ["The unverified role has been deleted so I can no longer mark new members as unverified or swap it for the verified role, please run /setup again with a new unverified role."]
+    );
+    assert!(!is_cached(&replay).await);
+}
+
+#[tokio::test]
+async fn forgets_guilds_the_bot_is_removed_from() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    Mock::given(method("POST"))
+        .and(path(format!("/api/v1/guild/{}/leave", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&replay.api)
+        .await;
+    api::get_guild(
+        &state(&replay.ctx).await.api(),
+        GuildId::new(replay.ids.guild),
+    )
+    .await
+    .unwrap();
+    assert!(is_cached(&replay).await);
+
+    replay
+        .event(
+            "GUILD_DELETE",
+            json!({ "id": replay.ids.guild.to_string(), "unavailable": false }),
+        )
+        .await;
+
+    assert!(!is_cached(&replay).await);
+    assert!(replay
+        .api_requests()
+        .await
+        .iter()
+        .any(|r| r.url.path() == format!("/api/v1/guild/{}/leave", replay.ids.guild)));
+}
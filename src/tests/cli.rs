@@ -0,0 +1,141 @@
+use serde_json::{json, Value};
+use serenity::all::{GuildId, HttpBuilder};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use super::harness::{config, unique_id, APP_ID};
+use crate::cli::{connect, purge_commands, register_commands};
+
+/// Mocks the application the commands belong to.
+async fn application(discord: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/api/v10/oauth2/applications/@me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": APP_ID.to_string(),
+            "name": "Verify",
+            "icon": null,
+            "description": "",
+            "bot_public": false,
+            "bot_require_code_grant": false,
+            "owner": null,
+            "verify_key": "0".repeat(64),
+            "team": null,
+        })))
+        .mount(discord)
+        .await;
+}
+
+/// A command as Discord returns it.
+fn command(id: u64, guild: Option<u64>, mut body: Value) -> Value {
+    body["id"] = json!(id.to_string());
+    body["application_id"] = json!(APP_ID.to_string());
+    body["version"] = json!(id.to_string());
+    body["type"] = body.get("type").cloned().unwrap_or(json!(1));
+    body["description"] = body.get("description").cloned().unwrap_or(json!(""));
+    if let Some(guild) = guild {
+        body["guild_id"] = json!(guild.to_string());
+    }
+    body
+}
+
+#[tokio::test]
+async fn register_commands_creates_commands() {
+    let discord = MockServer::start().await;
+    application(&discord).await;
+    let commands = format!("/api/v10/applications/{APP_ID}/commands");
+    let stale = unique_id();
+    Mock::given(method("GET"))
+        .and(path(commands.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([command(
+            stale,
+            None,
+            json!({ "name": "stale", "description": "Gone." })
+        )])))
+        .mount(&discord)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(commands.as_str()))
+        .respond_with(|request: &Request| {
+            let body = serde_json::from_slice(&request.body).unwrap();
+            ResponseTemplate::new(200).set_body_json(command(unique_id(), None, body))
+        })
+        .mount(&discord)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(format!("{commands}/{stale}")))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&discord)
+        .await;
+
+    let http = connect(
+        HttpBuilder::new("replay")
+            .proxy(discord.uri())
+            .ratelimiter_disabled(true),
+    )
+    .await
+    .unwrap();
+    register_commands(&http, &config(discord.uri()), None)
+        .await
+        .unwrap();
+
+    let created: Vec<String> = discord
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.method.as_str() == "POST")
+        .map(|r| {
+            r.body_json::<Value>().unwrap()["name"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert!(created.contains(&"verify".to_string()), "{created:?}");
+}
+
+#[tokio::test]
+async fn purge_commands_deletes_guild_commands() {
+    let discord = MockServer::start().await;
+    application(&discord).await;
+    let guild = unique_id();
+    let commands = format!("/api/v10/applications/{APP_ID}/guilds/{guild}/commands");
+    let (first, second) = (unique_id(), unique_id());
+    Mock::given(method("GET"))
+        .and(path(commands.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            command(
+                first,
+                Some(guild),
+                json!({ "name": "verify", "description": "Verify." })
+            ),
+            command(
+                second,
+                Some(guild),
+                json!({ "name": "setup", "description": "Setup." })
+            ),
+        ])))
+        .mount(&discord)
+        .await;
+    for id in [first, second] {
+        Mock::given(method("DELETE"))
+            .and(path(format!("{commands}/{id}")))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&discord)
+            .await;
+    }
+
+    let http = connect(
+        HttpBuilder::new("replay")
+            .proxy(discord.uri())
+            .ratelimiter_disabled(true),
+    )
+    .await
+    .unwrap();
+    let mut config = config(discord.uri());
+    config.purge_guild_ids = vec![GuildId::new(guild)];
+    purge_commands(&http, &config, vec![]).await.unwrap();
+    assert_eq!(http.application_id().map(|id| id.get()), Some(APP_ID));
+}
@@ -0,0 +1,60 @@
+use serenity::model::prelude::UserId;
+use serenity::model::Timestamp;
+
+use crate::commands::export::{csv_field, to_csv, Privacy, Row};
+
+fn row() -> Row {
+    Row {
+        user_id: UserId::new(7),
+        username: "alice".to_string(),
+        display_name: "Alice, \"Al\"".to_string(),
+        has_role: true,
+        verified: true,
+        linked: Some(Timestamp::from_unix_timestamp(1_700_000_000).unwrap()),
+    }
+}
+
+#[test]
+fn quotes_fields_when_needed() {
+    assert_eq!(csv_field("plain"), "plain");
+    assert_eq!(csv_field("a,b"), "\"a,b\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+}
+
+#[test]
+fn escapes_formulas() {
+    assert_eq!(csv_field("=1+1"), "'=1+1");
+    assert_eq!(csv_field("@everyone"), "'@everyone");
+    assert_eq!(csv_field("=SUM(A1,A2)"), "\"'=SUM(A1,A2)\"");
+}
+
+#[test]
+fn includes_every_column_by_default() {
+    let csv = to_csv(&[row()], Privacy::default());
+
+    assert_eq!(
+        csv,
+        "Discord ID,Username,Display name,Has verified role,Verified,Discord linked date\r\n\
+         7,alice,\"Alice, \"\"Al\"\"\",Yes,Yes,2023-11-14T22:13:20Z\r\n"
+    );
+}
+
+#[test]
+fn leaves_out_private_columns() {
+    let mut unverified = row();
+    unverified.has_role = false;
+    unverified.verified = false;
+    unverified.linked = None;
+    let privacy = Privacy {
+        names: false,
+        link_dates: false,
+    };
+
+    let csv = to_csv(&[row(), unverified], privacy);
+
+    assert_eq!(
+        csv,
+        "Discord ID,Has verified role,Verified\r\n7,Yes,Yes\r\n7,No,No\r\n"
+    );
+}
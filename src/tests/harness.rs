@@ -0,0 +1,519 @@
+//! Replays recorded interactions through `dispatch_commands`.
+//!
+//! Each replay runs a real serenity client against a fake gateway, which sends recorded events
+//! such as `INTERACTION_CREATE`, a fake Discord HTTP API and a fake verify API, both of which
+//! record every request so tests can assert on the responses and role changes made.
+//!
+//! Fixtures live in `tests/fixtures` and use placeholders such as `$GUILD_ID` for ids, so every
+//! test gets its own guild and user and the caches shared between tests don't interfere.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use serenity::all::{
+    ClientBuilder, Guild, GuildId, HttpBuilder, Interaction, Role, RoleId, UnavailableGuild,
+};
+use serenity::async_trait;
+use serenity::model::gateway::Ready;
+use serenity::prelude::*;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use crate::commands::cleanup::{guild_left, role_deleted};
+use crate::commands::{deliver_component, deliver_modal, ApiClient};
+use crate::config::{Config, RateLimit};
+use crate::state::AppState;
+use crate::store;
+use crate::{dispatch_commands, INTENTS};
+
+pub const BOT_ID: u64 = 1008451962710937650;
+pub const APP_ID: u64 = 1008451962710937651;
+pub const BOT_ROLE_ID: u64 = 1008452512328429600;
+pub const OWNER_ID: u64 = 249567112458125312;
+const TOKEN: &str = "aW50ZXJhY3Rpb246MTIxMzc0MDU2MDkzNDgwMTQ1ODp0b2tlbg";
+const MODAL_TOKEN: &str = "aW50ZXJhY3Rpb246MTIxMzc0MDU2MDkzNDgwMTQ2MTp0b2tlbg";
+
+/// Mocks mounted by tests take priority over these defaults.
+const DEFAULT_PRIORITY: u8 = 10;
+
+/// How long to wait for the bot before failing a test.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1213000000000000000);
+
+/// Gets an id no other test is using.
+pub fn unique_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The ids a replay fills the fixtures in with.
+#[derive(Copy, Clone, Debug)]
+pub struct Ids {
+    pub guild: u64,
+    pub user: u64,
+    pub role: u64,
+}
+
+impl Ids {
+    pub fn new() -> Ids {
+        Ids {
+            guild: unique_id(),
+            user: unique_id(),
+            role: unique_id(),
+        }
+    }
+}
+
+/// A role change the bot made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RoleChange {
+    Add(u64, u64),
+    Remove(u64, u64),
+}
+
+struct Recorder {
+    ready: UnboundedSender<Context>,
+    results: UnboundedSender<anyhow::Result<()>>,
+    /// Signalled once a guild or role event has been handled.
+    events: UnboundedSender<()>,
+}
+
+#[async_trait]
+impl EventHandler for Recorder {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        self.ready.send(ctx).ok();
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) => {
+                self.results
+                    .send(dispatch_commands(&ctx, command).await)
+                    .ok();
+            }
+            Interaction::Modal(modal) => {
+                deliver_modal(modal);
+            }
+            Interaction::Component(press) => {
+                deliver_component(press);
+            }
+            _ => {}
+        }
+    }
+
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _: Option<Guild>) {
+        if !incomplete.unavailable {
+            guild_left(&ctx, incomplete.id).await;
+        }
+        self.events.send(()).ok();
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        role_id: RoleId,
+        _: Option<Role>,
+    ) {
+        role_deleted(&ctx, guild_id, role_id).await;
+        self.events.send(()).ok();
+    }
+}
+
+pub struct Replay {
+    pub ids: Ids,
+    /// The fake Discord HTTP API.
+    pub discord: MockServer,
+    /// The fake verify API.
+    pub api: MockServer,
+    /// The connected client's context, for running background jobs directly.
+    pub ctx: Context,
+    /// The intents the client identified with.
+    pub intents: GatewayIntents,
+    events: UnboundedSender<(&'static str, Value)>,
+    results: UnboundedReceiver<anyhow::Result<()>>,
+    handled: UnboundedReceiver<()>,
+}
+
+/// The configuration replays run with, using `api_url` as the verify API.
+pub fn config(api_url: String) -> Config {
+    Config {
+        discord_token: "replay".to_string(),
+        api_key: "replay".to_string(),
+        api_url,
+        display_url: "https://society.ecs.soton.ac.uk/verify".parse().unwrap(),
+        link_secret: None,
+        link_ttl: Duration::from_secs(60 * 15),
+        test_guild_id: None,
+        purge_guild_ids: vec![],
+        operator_guild_id: None,
+        operator_ids: vec![],
+        verify_user_limit: RateLimit {
+            limit: 1,
+            window: Duration::from_secs(10),
+        },
+        verify_guild_limit: RateLimit {
+            limit: 30,
+            window: Duration::from_secs(60),
+        },
+        role_change_interval: Duration::from_millis(250),
+        retry_horizon: Duration::from_secs(60 * 60),
+        sync_interval: Duration::ZERO,
+        interactions_addr: None,
+        public_key: None,
+        // Keeps the history commands record out of the working directory.
+        data_dir: env::temp_dir().join(format!("verify-bot-tests-{}", std::process::id())),
+    }
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.json"))
+}
+
+impl Replay {
+    /// Starts the fakes and connects a client to them, returning once it is ready.
+    pub async fn start(ids: Ids) -> Replay {
+        let discord = MockServer::start().await;
+        let api = MockServer::start().await;
+        let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gateway_url = format!("ws://{}", gateway.local_addr().unwrap());
+
+        Mock::given(method("GET"))
+            .and(path("/api/v10/gateway"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "url": gateway_url })))
+            .with_priority(DEFAULT_PRIORITY)
+            .mount(&discord)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/api/v10/interactions/\d+/[^/]+/callback$"))
+            .respond_with(ResponseTemplate::new(204))
+            .with_priority(DEFAULT_PRIORITY)
+            .mount(&discord)
+            .await;
+        Mock::given(path_regex(r"^/api/v10/webhooks/\d+/[^/]+"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(fixture_with(ids, "message")))
+            .with_priority(DEFAULT_PRIORITY)
+            .mount(&discord)
+            .await;
+        Mock::given(path_regex(r"^/api/v10/guilds/\d+/members/\d+/roles/\d+$"))
+            .respond_with(ResponseTemplate::new(204))
+            .with_priority(DEFAULT_PRIORITY)
+            .mount(&discord)
+            .await;
+
+        let (events, event_recv) = unbounded_channel();
+        let (identified, identify) = oneshot::channel();
+        let ready = fixture_with(ids, "ready")
+            .to_string()
+            .replace("$GATEWAY_URL", &gateway_url);
+        tokio::spawn(run_gateway(
+            gateway,
+            serde_json::from_str(&ready).unwrap(),
+            event_recv,
+            identified,
+        ));
+
+        let config = config(api.uri());
+        store::init(config.data_dir.clone());
+        let state = AppState::new(config.clone(), ApiClient::new(&config).unwrap());
+        let http = HttpBuilder::new(&config.discord_token)
+            .proxy(discord.uri())
+            .ratelimiter_disabled(true)
+            .build();
+        let (ready_send, mut ready_recv) = unbounded_channel();
+        let (results_send, results) = unbounded_channel();
+        let (handled_send, handled) = unbounded_channel();
+        let mut client = ClientBuilder::new_with_http(http, INTENTS)
+            .event_handler(Recorder {
+                ready: ready_send,
+                results: results_send,
+                events: handled_send,
+            })
+            .type_map_insert::<AppState>(Arc::new(state))
+            .await
+            .unwrap();
+        tokio::spawn(async move { client.start().await });
+        let ctx = tokio::time::timeout(TIMEOUT, ready_recv.recv())
+            .await
+            .expect("The client never became ready")
+            .unwrap();
+        let identify: Value = identify.await.unwrap();
+
+        Replay {
+            ids,
+            discord,
+            api,
+            ctx,
+            intents: GatewayIntents::from_bits_truncate(identify["d"]["intents"].as_u64().unwrap()),
+            events,
+            results,
+            handled,
+        }
+    }
+
+    /// Registers the guild with the verify API, using `ids.role` as its verified role.
+    pub async fn registered(&self) {
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v1/guild/{}", self.ids.guild)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "roleId": self.ids.role.to_string(),
+                "approved": true,
+            })))
+            .mount(&self.api)
+            .await;
+    }
+
+    /// Registers the guild with a member role for paid society members.
+    pub async fn registered_with_member_role(&self, member_role: u64) {
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v1/guild/{}", self.ids.guild)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "roleId": self.ids.role.to_string(),
+                "memberRoleId": member_role.to_string(),
+                "approved": true,
+            })))
+            .mount(&self.api)
+            .await;
+    }
+
+    /// Makes the verify API say whether the user has paid for society membership.
+    pub async fn paid_member(&self, member: bool) {
+        Mock::given(method("GET"))
+            .and(path("/api/v1/membership"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "member": member })))
+            .mount(&self.api)
+            .await;
+    }
+
+    /// Makes the verify API say whether the user has verified.
+    pub async fn verified(&self, verified: bool) {
+        Mock::given(method("GET"))
+            .and(path("/api/v1/verified"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "verified": verified,
+                "roleId": self.ids.role.to_string(),
+                "sotonLinkedDate": "2024-02-03T14:20:51.000Z",
+                "discordLinkedDate": "2024-02-03T14:21:09.482Z",
+            })))
+            .mount(&self.api)
+            .await;
+    }
+
+    /// Loads a fixture, filling in this replay's ids.
+    pub fn fixture(&self, name: &str) -> Value {
+        fixture_with(self.ids, name)
+    }
+
+    /// Sends a recorded interaction without waiting for it to be handled.
+    pub fn send(&self, name: &str) {
+        self.events
+            .send(("INTERACTION_CREATE", self.fixture(name)))
+            .unwrap();
+    }
+
+    /// Sends a guild or role event, returning once the bot has handled it.
+    pub async fn event(&mut self, kind: &'static str, data: Value) {
+        self.events.send((kind, data)).unwrap();
+        tokio::time::timeout(TIMEOUT, self.handled.recv())
+            .await
+            .unwrap_or_else(|_| panic!("The bot never handled {kind}"));
+    }
+
+    /// Gets the result of the next command `dispatch_commands` finishes handling.
+    pub async fn result(&mut self) -> anyhow::Result<()> {
+        tokio::time::timeout(TIMEOUT, self.results.recv())
+            .await
+            .expect("The command was never handled")
+            .unwrap()
+    }
+
+    /// Replays a recorded command interaction, returning the result of `dispatch_commands`.
+    pub async fn dispatch(&mut self, name: &str) -> anyhow::Result<()> {
+        self.send(name);
+        self.result().await
+    }
+
+    /// Waits until the bot has made a request matching `method` and `path`.
+    pub async fn wait_for(&self, method: &str, path: &str) {
+        tokio::time::timeout(TIMEOUT, async {
+            while !self
+                .discord_requests()
+                .await
+                .iter()
+                .any(|r| r.method.as_str() == method && r.url.path().ends_with(path))
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("The bot never sent {method} {path}"));
+    }
+
+    /// Waits until the bot has made a request to the verify API matching `method` and `path`.
+    pub async fn wait_for_api(&self, method: &str, path: &str) {
+        tokio::time::timeout(TIMEOUT, async {
+            while !self
+                .api_requests()
+                .await
+                .iter()
+                .any(|r| r.method.as_str() == method && r.url.path() == path)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("The bot never sent {method} {path} to the verify API"));
+    }
+
+    pub async fn discord_requests(&self) -> Vec<Request> {
+        self.discord.received_requests().await.unwrap_or_default()
+    }
+
+    pub async fn api_requests(&self) -> Vec<Request> {
+        self.api.received_requests().await.unwrap_or_default()
+    }
+
+    /// Everything the bot said in reply to interactions, in order.
+    pub async fn replies(&self) -> Vec<String> {
+        self.discord_requests()
+            .await
+            .iter()
+            .filter(|r| {
+                r.url.path().contains("/interactions/") || r.url.path().contains("/webhooks/")
+            })
+            .filter_map(|r| {
+                let body: Value = serde_json::from_slice(&r.body).ok()?;
+                let content = body.get("data").unwrap_or(&body).get("content")?.as_str()?;
+                Some(content.to_string())
+            })
+            .collect()
+    }
+
+    /// The interaction response types the bot sent, such as 4 for a message or 9 for a modal.
+    pub async fn response_types(&self) -> Vec<u64> {
+        self.discord_requests()
+            .await
+            .iter()
+            .filter(|r| r.url.path().ends_with("/callback"))
+            .filter_map(|r| serde_json::from_slice::<Value>(&r.body).ok()?["type"].as_u64())
+            .collect()
+    }
+
+    /// Every role the bot added or removed, in order.
+    pub async fn role_changes(&self) -> Vec<RoleChange> {
+        self.discord_requests()
+            .await
+            .iter()
+            .filter_map(|r| {
+                let segments = r.url.path_segments()?.collect::<Vec<_>>();
+                let ["api", "v10", "guilds", _, "members", user, "roles", role] = segments[..]
+                else {
+                    return None;
+                };
+                let (user, role) = (user.parse().ok()?, role.parse().ok()?);
+                match r.method.as_str() {
+                    "PUT" => Some(RoleChange::Add(user, role)),
+                    "DELETE" => Some(RoleChange::Remove(user, role)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Loads a fixture, filling in `ids`.
+pub fn fixture_with(ids: Ids, name: &str) -> Value {
+    let fixture = fs::read_to_string(fixture_path(name))
+        .unwrap_or_else(|e| panic!("Could not read fixture {name}: {e}"))
+        .replace("$GUILD_ID", &ids.guild.to_string())
+        .replace("$USER_ID", &ids.user.to_string())
+        .replace("$ROLE_ID", &ids.role.to_string())
+        .replace("$BOT_ID", &BOT_ID.to_string())
+        .replace("$APP_ID", &APP_ID.to_string())
+        .replace("$OWNER_ID", &OWNER_ID.to_string())
+        .replace("$MODAL_TOKEN", MODAL_TOKEN)
+        .replace("$TOKEN", TOKEN);
+    serde_json::from_str(&fixture).unwrap_or_else(|e| panic!("Invalid fixture {name}: {e}"))
+}
+
+/// A guild member with the given roles.
+pub fn member(ids: Ids, user_id: u64, roles: &[u64]) -> Value {
+    let mut member = fixture_with(ids, "member");
+    member["user"]["id"] = json!(user_id.to_string());
+    member["roles"] = json!(roles.iter().map(u64::to_string).collect::<Vec<_>>());
+    member
+}
+
+/// A role at the given position.
+pub fn role(ids: Ids, role_id: u64, position: u16) -> Value {
+    let mut role = fixture_with(ids, "role");
+    role["id"] = json!(role_id.to_string());
+    role["position"] = json!(position);
+    role
+}
+
+/// Pretends to be Discord's gateway, identifying the client and then sending it `events` as
+/// dispatches. The client's identify payload is sent to `identified`.
+async fn run_gateway(
+    listener: TcpListener,
+    ready: Value,
+    mut events: UnboundedReceiver<(&'static str, Value)>,
+    identified: oneshot::Sender<Value>,
+) {
+    let mut identified = Some(identified);
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+    let send = |payload: Value| Message::Text(payload.to_string());
+
+    ws.send(send(
+        json!({ "op": 10, "d": { "heartbeat_interval": 45000 } }),
+    ))
+    .await
+    .unwrap();
+    let mut seq = 0;
+    loop {
+        tokio::select! {
+            Some((kind, event)) = events.recv() => {
+                seq += 1;
+                let dispatch = json!({ "op": 0, "t": kind, "s": seq, "d": event });
+                if ws.send(send(dispatch)).await.is_err() {
+                    return;
+                }
+            }
+            message = ws.next() => {
+                let Some(Ok(Message::Text(text))) = message else {
+                    return;
+                };
+                let payload: Value = serde_json::from_str(&text).unwrap();
+                let reply = match payload["op"].as_u64() {
+                    // Heartbeat
+                    Some(1) => json!({ "op": 11 }),
+                    // Identify
+                    Some(2) => {
+                        if let Some(identified) = identified.take() {
+                            identified.send(payload.clone()).ok();
+                        }
+                        seq += 1;
+                        json!({ "op": 0, "t": "READY", "s": seq, "d": ready })
+                    }
+                    _ => continue,
+                };
+                if ws.send(send(reply)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderValue};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use wiremock::matchers::{method, path_regex};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{unique_id, Ids, Replay, RoleChange};
+use crate::interactions::{serve, verify_signature};
+use crate::state::state;
+
+const BODY: &[u8] = br#"{"type":1}"#;
+const TIMESTAMP: &str = "1709382060";
+
+fn signature(key: &SigningKey, timestamp: &str, body: &[u8]) -> String {
+    hex::encode(key.sign(&[timestamp.as_bytes(), body].concat()).to_bytes())
+}
+
+fn signed(key: &SigningKey, timestamp: &str, body: &[u8]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "X-Signature-Ed25519",
+        HeaderValue::from_str(&signature(key, timestamp, body)).unwrap(),
+    );
+    headers.insert(
+        "X-Signature-Timestamp",
+        HeaderValue::from_str(timestamp).unwrap(),
+    );
+    headers
+}
+
+#[test]
+fn accepts_signed_interactions() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let headers = signed(&key, TIMESTAMP, BODY);
+    assert!(verify_signature(&key.verifying_key(), &headers, BODY));
+}
+
+#[test]
+fn rejects_changed_bodies() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let headers = signed(&key, TIMESTAMP, BODY);
+    assert!(!verify_signature(
+        &key.verifying_key(),
+        &headers,
+        br#"{"type":2}"#
+    ));
+}
+
+#[test]
+fn rejects_changed_timestamps() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let mut headers = signed(&key, TIMESTAMP, BODY);
+    headers.insert(
+        "X-Signature-Timestamp",
+        HeaderValue::from_static("1709382061"),
+    );
+    assert!(!verify_signature(&key.verifying_key(), &headers, BODY));
+}
+
+#[test]
+fn rejects_other_keys() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let other = SigningKey::from_bytes(&[8; 32]);
+    let headers = signed(&other, TIMESTAMP, BODY);
+    assert!(!verify_signature(&key.verifying_key(), &headers, BODY));
+}
+
+#[test]
+fn rejects_missing_and_malformed_signatures() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    assert!(!verify_signature(
+        &key.verifying_key(),
+        &HeaderMap::new(),
+        BODY
+    ));
+
+    let mut headers = signed(&key, TIMESTAMP, BODY);
+    headers.insert("X-Signature-Ed25519", HeaderValue::from_static("not hex"));
+    assert!(!verify_signature(&key.verifying_key(), &headers, BODY));
+}
+
+/// Serves the interactions endpoint for `replay`, returning a function that posts signed
+/// interactions to it.
+async fn endpoint(replay: &Replay) -> impl Fn(Value) -> reqwest::RequestBuilder {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let state = state(&replay.ctx).await;
+    let mut config = (*state.config()).clone();
+    config.public_key = Some(key.verifying_key());
+    state.replace_config(config, state.api());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/interactions", listener.local_addr().unwrap());
+    tokio::spawn(serve(replay.ctx.clone(), listener));
+    move |interaction| {
+        let body = interaction.to_string();
+        reqwest::Client::new()
+            .post(&url)
+            .header(
+                "X-Signature-Ed25519",
+                signature(&key, TIMESTAMP, body.as_bytes()),
+            )
+            .header("X-Signature-Timestamp", TIMESTAMP)
+            .header("Content-Type", "application/json")
+            .body(body)
+    }
+}
+
+#[tokio::test]
+async fn delivers_button_presses_to_the_waiting_command() {
+    let replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(true).await;
+    let post = endpoint(&replay).await;
+    let mut message = replay.fixture("message");
+    message["id"] = json!(unique_id().to_string());
+    Mock::given(method("PATCH"))
+        .and(path_regex(r"/messages/@original$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&message))
+        .mount(&replay.discord)
+        .await;
+
+    let mut command = replay.fixture("verify");
+    let user = command["member"]["user"].clone();
+    let mut member = command["member"].clone();
+    member.as_object_mut().unwrap().remove("user");
+    command["data"] = json!({
+        "id": unique_id().to_string(),
+        "name": "Check verification",
+        "type": 2,
+        "target_id": replay.ids.user.to_string(),
+        "resolved": {
+            "users": { replay.ids.user.to_string(): user },
+            "members": { replay.ids.user.to_string(): member },
+        },
+    });
+    // The command keeps waiting for the press after the endpoint has replied.
+    tokio::spawn(post(command).send());
+    replay.wait_for("PATCH", "/messages/@original").await;
+    // It starts waiting for the press once it has the message.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut press = replay.fixture("verify");
+    press["id"] = json!(unique_id().to_string());
+    press["type"] = json!(3);
+    press["data"] = json!({ "custom_id": "assign-verified", "component_type": 2 });
+    press["message"] = message;
+    let response = post(press).send().await.unwrap();
+
+    assert_eq!(response.status(), 202);
+    // Delivering the press doesn't wait for the command to finish with it.
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !replay.replies().await.iter().any(|r| r.starts_with("Gave")) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("The press was never responded to");
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(replay.ids.user, replay.ids.role)]
+    );
+    // Both are deferred, the press because adding the role waits on the role queue.
+    assert_eq!(replay.response_types().await, [5, 6]);
+}
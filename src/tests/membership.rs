@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{unique_id, Ids, Replay, RoleChange};
+
+#[tokio::test]
+async fn gives_paid_members_the_member_role() {
+    let mut replay = Replay::start(Ids::new()).await;
+    let member_role = unique_id();
+    replay.registered_with_member_role(member_role).await;
+    replay.verified(true).await;
+    replay.paid_member(true).await;
+
+    replay.dispatch("verify").await.unwrap();
+    replay
+        .wait_for("PUT", &format!("/roles/{member_role}"))
+        .await;
+
+    assert_eq!(replay.replies().await, ["You have now been verified!"]);
+    assert_eq!(
+        replay.role_changes().await,
+        [
+            RoleChange::Add(replay.ids.user, replay.ids.role),
+            RoleChange::Add(replay.ids.user, member_role),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn takes_the_member_role_from_unpaid_members() {
+    let mut replay = Replay::start(Ids::new()).await;
+    let member_role = unique_id();
+    replay.registered_with_member_role(member_role).await;
+    replay.verified(true).await;
+    replay.paid_member(false).await;
+
+    replay.dispatch("verify").await.unwrap();
+    replay
+        .wait_for("DELETE", &format!("/roles/{member_role}"))
+        .await;
+
+    assert_eq!(
+        replay.role_changes().await,
+        [
+            RoleChange::Add(replay.ids.user, replay.ids.role),
+            RoleChange::Remove(replay.ids.user, member_role),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn verifies_even_if_the_membership_lookup_fails() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered_with_member_role(unique_id()).await;
+    replay.verified(true).await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/membership"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&replay.api)
+        .await;
+
+    replay.dispatch("verify").await.unwrap();
+    replay.wait_for_api("GET", "/api/v1/membership").await;
+
+    assert_eq!(replay.replies().await, ["You have now been verified!"]);
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(replay.ids.user, replay.ids.role)]
+    );
+}
+
+#[tokio::test]
+async fn replies_before_checking_membership() {
+    let mut replay = Replay::start(Ids::new()).await;
+    let member_role = unique_id();
+    replay.registered_with_member_role(member_role).await;
+    replay.verified(true).await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/membership"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "member": true }))
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&replay.api)
+        .await;
+
+    tokio::time::timeout(Duration::from_secs(2), replay.dispatch("verify"))
+        .await
+        .expect("/verify waited for the membership lookup")
+        .unwrap();
+
+    assert_eq!(replay.replies().await, ["You have now been verified!"]);
+}
@@ -0,0 +1,16 @@
+//! Mostly replays recorded interactions against fake Discord and verify APIs.
+
+mod cleanup;
+mod cli;
+mod export;
+mod harness;
+mod interactions;
+mod membership;
+mod ratelimit;
+mod registration;
+mod setup;
+mod stats;
+mod sync;
+mod telemetry;
+mod verify;
+mod verify_all;
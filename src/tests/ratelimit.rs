@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use crate::commands::ratelimit::RateLimiter;
+use crate::config::RateLimit;
+
+const LIMIT: RateLimit = RateLimit {
+    limit: 2,
+    window: Duration::from_millis(100),
+};
+
+#[test]
+fn blocks_once_the_limit_is_reached() {
+    let limiter = RateLimiter::new();
+
+    assert_eq!(limiter.try_acquire(1, LIMIT), Ok(()));
+    assert_eq!(limiter.try_acquire(1, LIMIT), Ok(()));
+    let wait = limiter.try_acquire(1, LIMIT).unwrap_err();
+
+    assert!(wait > Duration::ZERO && wait <= LIMIT.window);
+    assert_eq!(limiter.try_acquire(2, LIMIT), Ok(()));
+}
+
+#[test]
+fn allows_hits_again_after_the_window() {
+    let limiter = RateLimiter::new();
+    limiter.try_acquire(1, LIMIT).unwrap();
+    limiter.try_acquire(1, LIMIT).unwrap();
+
+    std::thread::sleep(LIMIT.window);
+
+    assert_eq!(limiter.try_acquire(1, LIMIT), Ok(()));
+}
+
+#[test]
+fn released_hits_dont_count() {
+    let limiter = RateLimiter::new();
+    limiter.try_acquire(1, LIMIT).unwrap();
+    limiter.try_acquire(1, LIMIT).unwrap();
+
+    limiter.release(&1);
+
+    assert_eq!(limiter.try_acquire(1, LIMIT), Ok(()));
+}
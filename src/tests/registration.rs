@@ -0,0 +1,75 @@
+use serde_json::{json, Value};
+use serenity::all::{Command, CommandOptionType, CreateCommand, CreateCommandOption, Permissions};
+use serenity::model::prelude::GuildId;
+
+use super::harness::{unique_id, APP_ID};
+use crate::registration::{canonical, Scope};
+
+fn setup() -> CreateCommand {
+    CreateCommand::new("setup")
+        .description("Sets your server up so that users can be verified.")
+        .dm_permission(false)
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Role, "role", "The verified role.")
+                .required(true),
+        )
+}
+
+/// `setup` as Discord returns it, with `dm_permission` only when it is registered globally.
+fn fetched(guild_id: Option<GuildId>) -> Value {
+    let mut command = json!({
+        "id": unique_id().to_string(),
+        "application_id": APP_ID.to_string(),
+        "version": unique_id().to_string(),
+        "type": 1,
+        "name": "setup",
+        "name_localizations": null,
+        "description": "Sets your server up so that users can be verified.",
+        "description_localizations": null,
+        "default_member_permissions": "8",
+        "nsfw": false,
+        "options": [{
+            "type": 8,
+            "name": "role",
+            "name_localizations": null,
+            "description": "The verified role.",
+            "description_localizations": null,
+            "required": true,
+        }],
+    });
+    match guild_id {
+        Some(guild_id) => command["guild_id"] = json!(guild_id.to_string()),
+        None => command["dm_permission"] = json!(false),
+    }
+    // Round trip through serenity like `sync_commands` does.
+    let command: Command = serde_json::from_value(command).unwrap();
+    serde_json::to_value(command).unwrap()
+}
+
+#[test]
+fn guild_commands_match_what_discord_returns() {
+    let guild_id = GuildId::new(unique_id());
+    let scope = Scope::Guild(guild_id);
+
+    assert_eq!(
+        canonical(serde_json::to_value(setup()).unwrap(), scope),
+        canonical(fetched(Some(guild_id)), scope)
+    );
+}
+
+#[test]
+fn global_commands_match_what_discord_returns() {
+    assert_eq!(
+        canonical(serde_json::to_value(setup()).unwrap(), Scope::Global),
+        canonical(fetched(None), Scope::Global)
+    );
+    // Still noticed when it changes.
+    assert_ne!(
+        canonical(
+            serde_json::to_value(setup().dm_permission(true)).unwrap(),
+            Scope::Global
+        ),
+        canonical(fetched(None), Scope::Global)
+    );
+}
@@ -0,0 +1,151 @@
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{member, role, Ids, Replay, BOT_ID, BOT_ROLE_ID};
+
+const BOT_POSITION: u16 = 5;
+
+/// Puts the bot and the verified role, at `position`, in the guild.
+async fn guild(replay: &Replay, position: u16) {
+    let ids = replay.ids;
+    let roles = json!([
+        role(ids, BOT_ROLE_ID, BOT_POSITION),
+        role(ids, ids.role, position)
+    ]);
+    let mut guild = replay.fixture("guild");
+    guild["roles"] = roles.clone();
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(guild))
+        .mount(&replay.discord)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}/roles", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(roles))
+        .mount(&replay.discord)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/api/v10/guilds/{}/members/{BOT_ID}",
+            ids.guild
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(member(ids, BOT_ID, &[BOT_ROLE_ID])))
+        .mount(&replay.discord)
+        .await;
+}
+
+async fn register(replay: &Replay, response: ResponseTemplate) {
+    Mock::given(method("POST"))
+        .and(path("/api/v1/guild/register"))
+        .respond_with(response)
+        .mount(&replay.api)
+        .await;
+}
+
+/// Runs /setup, filling in the modal once the bot has shown it.
+async fn setup(replay: &mut Replay) -> anyhow::Result<()> {
+    replay.send("setup");
+    replay.wait_for("POST", "/callback").await;
+    // Give the bot a moment to start listening for the modal.
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    replay.send("setup_modal");
+    replay.result().await
+}
+
+#[tokio::test]
+async fn registers_the_guild() {
+    let mut replay = Replay::start(Ids::new()).await;
+    guild(&replay, 1).await;
+    register(
+        &replay,
+        ResponseTemplate::new(200).set_body_json(json!({ "registered": true, "approved": true })),
+    )
+    .await;
+
+    setup(&mut replay).await.unwrap();
+
+    assert_eq!(replay.response_types().await, [9, 6]);
+    assert_eq!(replay.replies().await, ["Successfully set the server up!"]);
+    let register = replay
+        .api_requests()
+        .await
+        .into_iter()
+        .find(|r| r.url.path() == "/api/v1/guild/register")
+        .unwrap()
+        .body_json::<serde_json::Value>()
+        .unwrap();
+    assert_eq!(register["guildId"], json!(replay.ids.guild.to_string()));
+    assert_eq!(register["roleId"], json!(replay.ids.role.to_string()));
+    assert_eq!(register["name"], json!("Replay Society"));
+    assert_eq!(
+        register["inviteLink"],
+        json!("https://discord.gg/9SYG22wR4V")
+    );
+    assert_eq!(
+        register["susuLink"],
+        json!("https://www.susu.org/groups/ecss")
+    );
+}
+
+#[tokio::test]
+async fn asks_for_approval() {
+    let mut replay = Replay::start(Ids::new()).await;
+    guild(&replay, 1).await;
+    register(
+        &replay,
+        ResponseTemplate::new(200).set_body_json(json!({ "registered": true, "approved": false })),
+    )
+    .await;
+
+    setup(&mut replay).await.unwrap();
+
+    assert_eq!(
+        replay.replies().await,
+        ["Successfully set the server up! Please contact the ECSS web officer to get your server approved."]
+    );
+}
+
+#[tokio::test]
+async fn reports_guilds_already_registered() {
+    let mut replay = Replay::start(Ids::new()).await;
+    guild(&replay, 1).await;
+    register(&replay, ResponseTemplate::new(409)).await;
+
+    assert!(setup(&mut replay).await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["This server has already been registered, please contact the ECSS web officer to change its registration."]
+    );
+}
+
+#[tokio::test]
+async fn rejects_roles_above_the_bot() {
+    let mut replay = Replay::start(Ids::new()).await;
+    guild(&replay, BOT_POSITION + 1).await;
+
+    assert!(replay.dispatch("setup").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["Unable to use the verified role, please make sure my role has higher permissions than the verified role."]
+    );
+    assert!(replay.api_requests().await.is_empty());
+}
+
+#[tokio::test]
+async fn rejects_everyone() {
+    let mut ids = Ids::new();
+    ids.role = ids.guild;
+    let mut replay = Replay::start(ids).await;
+    guild(&replay, 0).await;
+
+    assert!(replay.dispatch("setup").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["Unable to use the verified role, please stop trying to crash this bot by using @everyone."]
+    );
+    assert!(replay.api_requests().await.is_empty());
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use serenity::model::prelude::UserId;
+use serenity::model::Timestamp;
+
+use crate::commands::stats::{format_duration, Stats};
+use crate::history::{prune, Event, Method};
+
+const DAY: i64 = 24 * 60 * 60;
+const NOW: i64 = 1_700_000_000;
+
+fn at(secs: i64) -> Timestamp {
+    Timestamp::from_unix_timestamp(NOW + secs).unwrap()
+}
+
+fn joined(user: u64, secs: i64) -> Event {
+    Event::Joined {
+        user_id: UserId::new(user),
+        at: at(secs),
+    }
+}
+
+fn verified(user: u64, secs: i64, method: Method) -> Event {
+    Event::Verified {
+        user_id: UserId::new(user),
+        at: at(secs),
+        method,
+    }
+}
+
+#[test]
+fn counts_recent_verifications_by_method() {
+    let events = [
+        verified(1, -40 * DAY, Method::VerifyAll),
+        verified(2, -20 * DAY, Method::Command),
+        verified(3, -DAY, Method::Join),
+        verified(4, -60, Method::Join),
+    ];
+
+    let stats = Stats::from_events(&events, at(0));
+
+    assert_eq!(stats.last_week, 2);
+    assert_eq!(stats.last_month, 3);
+    assert_eq!(stats.by_method[&Method::Join], 2);
+    assert_eq!(stats.by_method[&Method::Command], 1);
+    assert_eq!(stats.by_method[&Method::VerifyAll], 1);
+    assert!(!stats.by_method.contains_key(&Method::Moderator));
+    assert_eq!(stats.since, Some(at(-40 * DAY)));
+    assert_eq!(stats.median_wait, None);
+}
+
+#[test]
+fn median_wait_uses_the_latest_join() {
+    let events = [
+        joined(1, -1000),
+        verified(1, -900, Method::Command),
+        joined(2, -1000),
+        // They left without verifying, only the second join counts.
+        joined(2, -500),
+        verified(2, -200, Method::Join),
+        joined(3, -1000),
+        verified(3, -500, Method::Moderator),
+        // Verified before their join was recorded.
+        verified(4, -100, Method::VerifyAll),
+    ];
+
+    let stats = Stats::from_events(&events, at(0));
+
+    assert_eq!(stats.median_wait, Some(Duration::from_secs(300)));
+    assert_eq!(stats.last_week, 4);
+}
+
+#[test]
+fn prunes_events_older_than_the_longest_window() {
+    let mut events = vec![
+        joined(1, -40 * DAY),
+        verified(1, -31 * DAY, Method::Command),
+        joined(2, -30 * DAY),
+        verified(2, -DAY, Method::Join),
+    ];
+
+    prune(&mut events, at(0));
+
+    assert_eq!(
+        events,
+        [joined(2, -30 * DAY), verified(2, -DAY, Method::Join)]
+    );
+}
+
+#[test]
+fn formats_durations() {
+    assert_eq!(format_duration(Duration::ZERO), "0s");
+    assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+    assert_eq!(
+        format_duration(Duration::from_secs(2 * 3600 + 5 * 60 + 9)),
+        "2h 5m"
+    );
+    assert_eq!(format_duration(Duration::from_secs(3 * 86400 + 20)), "3d");
+}
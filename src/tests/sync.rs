@@ -0,0 +1,193 @@
+use serde_json::json;
+use serenity::model::prelude::GuildId;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{member, unique_id, Ids, Replay, RoleChange};
+use crate::sync::sync_guild;
+
+/// Registers the guild with member and alumni roles.
+async fn registered(replay: &Replay, member_role: u64, alumni_role: u64) {
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "roleId": replay.ids.role.to_string(),
+            "memberRoleId": member_role.to_string(),
+            "alumniRoleId": alumni_role.to_string(),
+            "approved": true,
+        })))
+        .mount(&replay.api)
+        .await;
+}
+
+/// Makes the verify API report `status` for `user_id`.
+async fn status(replay: &Replay, user_id: u64, status: &str) {
+    Mock::given(method("GET"))
+        .and(path("/api/v1/verified"))
+        .and(body_partial_json(json!({ "userId": user_id.to_string() })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "verified": true,
+            "roleId": replay.ids.role.to_string(),
+            "sotonLinkedDate": "2024-02-03T14:20:51.000Z",
+            "discordLinkedDate": "2024-02-03T14:21:09.482Z",
+            "status": status,
+        })))
+        .mount(&replay.api)
+        .await;
+}
+
+/// Makes the verify API say whether `user_id` has paid for society membership.
+async fn paid_member(replay: &Replay, user_id: u64, member: bool) {
+    Mock::given(method("GET"))
+        .and(path("/api/v1/membership"))
+        .and(body_partial_json(json!({ "userId": user_id.to_string() })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "member": member })))
+        .mount(&replay.api)
+        .await;
+}
+
+#[tokio::test]
+async fn moves_graduates_to_the_alumni_role() {
+    let replay = Replay::start(Ids::new()).await;
+    let ids = replay.ids;
+    let (member_role, alumni_role) = (unique_id(), unique_id());
+    let (student, graduate, lapsed, unverified) =
+        (unique_id(), unique_id(), unique_id(), unique_id());
+    registered(&replay, member_role, alumni_role).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}/members", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            member(ids, student, &[ids.role]),
+            member(ids, graduate, &[ids.role, member_role]),
+            member(ids, lapsed, &[ids.role, member_role]),
+            member(ids, unverified, &[]),
+        ])))
+        .mount(&replay.discord)
+        .await;
+    status(&replay, student, "student").await;
+    status(&replay, graduate, "graduate").await;
+    status(&replay, lapsed, "student").await;
+    paid_member(&replay, student, true).await;
+    paid_member(&replay, graduate, true).await;
+    paid_member(&replay, lapsed, false).await;
+    let graduate_path = format!("/api/v10/guilds/{}/members/{graduate}", ids.guild);
+    let graduate_member = member(ids, graduate, &[ids.role, member_role]);
+    Mock::given(path(&graduate_path))
+        .respond_with(ResponseTemplate::new(200).set_body_json(graduate_member))
+        .mount(&replay.discord)
+        .await;
+
+    sync_guild(&replay.ctx, GuildId::new(ids.guild))
+        .await
+        .unwrap();
+
+    let mut changes = replay.role_changes().await;
+    changes.sort_by_key(|c| format!("{c:?}"));
+    assert_eq!(
+        changes,
+        [
+            RoleChange::Add(student, member_role),
+            RoleChange::Remove(lapsed, member_role),
+        ]
+    );
+    let swap = replay
+        .discord_requests()
+        .await
+        .into_iter()
+        .find(|r| r.method.as_str() == "PATCH" && r.url.path() == graduate_path)
+        .expect("The graduate's roles were never changed");
+    assert_eq!(
+        swap.body_json::<serde_json::Value>().unwrap()["roles"],
+        json!([member_role.to_string(), alumni_role.to_string()])
+    );
+}
+
+#[tokio::test]
+async fn skips_guilds_without_optional_roles() {
+    let replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+
+    sync_guild(&replay.ctx, GuildId::new(replay.ids.guild))
+        .await
+        .unwrap();
+
+    assert!(replay.role_changes().await.is_empty());
+    assert!(!replay
+        .api_requests()
+        .await
+        .iter()
+        .any(|r| r.url.path() != format!("/api/v1/guild/{}", replay.ids.guild)));
+}
+
+#[tokio::test]
+async fn looks_members_up_in_batches() {
+    let replay = Replay::start(Ids::new()).await;
+    let ids = replay.ids;
+    let (member_role, alumni_role) = (unique_id(), unique_id());
+    let (student, graduate, lapsed) = (unique_id(), unique_id(), unique_id());
+    registered(&replay, member_role, alumni_role).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}/members", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            member(ids, student, &[ids.role]),
+            member(ids, graduate, &[ids.role]),
+            member(ids, lapsed, &[member_role]),
+        ])))
+        .mount(&replay.discord)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/verified/batch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "verified": [student.to_string(), graduate.to_string()],
+            "graduates": [graduate.to_string()],
+        })))
+        .mount(&replay.api)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/membership/batch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "members": [student.to_string(), graduate.to_string()],
+        })))
+        .mount(&replay.api)
+        .await;
+    let graduate_path = format!("/api/v10/guilds/{}/members/{graduate}", ids.guild);
+    Mock::given(path(&graduate_path))
+        .respond_with(ResponseTemplate::new(200).set_body_json(member(ids, graduate, &[ids.role])))
+        .mount(&replay.discord)
+        .await;
+
+    sync_guild(&replay.ctx, GuildId::new(ids.guild))
+        .await
+        .unwrap();
+
+    let mut changes = replay.role_changes().await;
+    changes.sort_by_key(|c| format!("{c:?}"));
+    // The graduate's member role is left for the next sync, their roles were just swapped.
+    assert_eq!(
+        changes,
+        [
+            RoleChange::Add(student, member_role),
+            RoleChange::Remove(lapsed, member_role),
+        ]
+    );
+    assert!(replay
+        .discord_requests()
+        .await
+        .iter()
+        .any(|r| r.method.as_str() == "PATCH" && r.url.path() == graduate_path));
+    let mut requests = replay
+        .api_requests()
+        .await
+        .iter()
+        .map(|r| format!("{} {}", r.method, r.url.path()))
+        .collect::<Vec<_>>();
+    requests.sort();
+    assert_eq!(
+        requests,
+        [
+            "GET /api/v1/guild/".to_string() + &ids.guild.to_string(),
+            "POST /api/v1/membership/batch".to_string(),
+            "POST /api/v1/verified/batch".to_string(),
+        ]
+    );
+}
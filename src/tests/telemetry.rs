@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+
+use tracing::{info_span, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::telemetry::LogBridge;
+
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl log::Log for Capture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        LINES
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn logs_events_with_their_spans() {
+    log::set_logger(&Capture).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let subscriber = Registry::default().with(LogBridge);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let interaction = info_span!("interaction", id = 42, command = "verify", user = 7);
+        let _interaction = interaction.enter();
+        let request = info_span!("get_guild", guild = 3);
+        let _request = request.enter();
+        warn!(status = 500, "Could not get guild.");
+        tracing::info!("Not logged as it is below the log level.");
+    });
+
+    assert_eq!(
+        *LINES.lock().unwrap(),
+        ["WARN interaction{id=42 command=verify user=7}: get_guild{guild=3}: Could not get guild. status=500"]
+    );
+}
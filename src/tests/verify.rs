@@ -0,0 +1,137 @@
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{Ids, Replay, RoleChange};
+
+#[tokio::test]
+async fn adds_the_verified_role() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(true).await;
+
+    replay.dispatch("verify").await.unwrap();
+
+    assert_eq!(replay.response_types().await, [5]);
+    assert_eq!(replay.replies().await, ["You have now been verified!"]);
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(replay.ids.user, replay.ids.role)]
+    );
+}
+
+#[tokio::test]
+async fn links_unverified_users_to_the_website() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(false).await;
+
+    replay.dispatch("verify").await.unwrap();
+
+    assert_eq!(
+        replay.replies().await,
+        ["Please verify yourself by going to https://society.ecs.soton.ac.uk/verify and then run this command again."]
+    );
+    assert_eq!(replay.role_changes().await, []);
+}
+
+#[tokio::test]
+async fn rejects_unregistered_guilds() {
+    let mut replay = Replay::start(Ids::new()).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&replay.api)
+        .await;
+
+    assert!(replay.dispatch("verify").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["It looks like your server doesn't support this bot, please contact the admins so they can run /setup."]
+    );
+    assert!(replay
+        .api_requests()
+        .await
+        .iter()
+        .all(|r| r.url.path() != "/api/v1/verified"));
+}
+
+#[tokio::test]
+async fn reports_the_verify_service_being_down() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/verified"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&replay.api)
+        .await;
+
+    assert!(replay.dispatch("verify").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["The verification service is currently unavailable, please try again in a few minutes."]
+    );
+    assert_eq!(replay.role_changes().await, []);
+}
+
+#[tokio::test]
+async fn reports_responses_that_cant_be_decoded() {
+    let mut replay = Replay::start(Ids::new()).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>"))
+        .mount(&replay.api)
+        .await;
+
+    assert!(replay.dispatch("verify").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["Something went wrong while checking your verification, please try again later."]
+    );
+}
+
+#[tokio::test]
+async fn reports_being_unable_to_add_the_role() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(true).await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"/roles/\d+$"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(serde_json::json!({
+            "message": "Missing Permissions",
+            "code": 50013,
+        })))
+        .mount(&replay.discord)
+        .await;
+
+    assert!(replay.dispatch("verify").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["I was unable to add the verified role, please make sure my role has higher permissions than the verified role."]
+    );
+}
+
+#[tokio::test]
+async fn rate_limits_users() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(false).await;
+
+    replay.dispatch("verify").await.unwrap();
+    replay.dispatch("verify").await.unwrap();
+
+    let replies = replay.replies().await;
+    assert_eq!(replies.len(), 2);
+    assert!(replies[1].starts_with("Slow down! Please try again in "));
+    let checks = replay.api_requests().await;
+    assert_eq!(
+        checks
+            .iter()
+            .filter(|r| r.url.path() == "/api/v1/verified")
+            .count(),
+        1
+    );
+}
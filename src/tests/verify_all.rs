@@ -0,0 +1,192 @@
+use serde_json::json;
+use serenity::all::{GuildId, UserId};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use super::harness::{member, unique_id, Ids, Replay, RoleChange};
+use crate::commands::api::{self, ApiError};
+use crate::state::state;
+
+/// Puts the user, an already verified member and a bot in the guild.
+async fn members(replay: &Replay) -> u64 {
+    let ids = replay.ids;
+    let already_verified = unique_id();
+    let mut bot = member(ids, unique_id(), &[]);
+    bot["user"]["bot"] = json!(true);
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}/members", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            member(ids, ids.user, &[]),
+            member(ids, already_verified, &[ids.role]),
+            bot,
+        ])))
+        .mount(&replay.discord)
+        .await;
+    already_verified
+}
+
+#[tokio::test]
+async fn verifies_members_in_a_batch() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    members(&replay).await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/verified/batch"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "verified": [replay.ids.user.to_string()] })),
+        )
+        .expect(1)
+        .mount(&replay.api)
+        .await;
+
+    replay.dispatch("verify_all").await.unwrap();
+
+    assert_eq!(
+        replay.replies().await,
+        ["Successfully completed re-verifications. Was able to verify 1 member."]
+    );
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(replay.ids.user, replay.ids.role)]
+    );
+    let batch = replay
+        .api_requests()
+        .await
+        .into_iter()
+        .find(|r| r.url.path() == "/api/v1/verified/batch")
+        .unwrap();
+    assert_eq!(
+        batch.body_json::<serde_json::Value>().unwrap()["userIds"],
+        json!([replay.ids.user.to_string()])
+    );
+}
+
+#[tokio::test]
+async fn falls_back_to_checking_members_individually() {
+    let mut replay = Replay::start(Ids::new()).await;
+    replay.registered().await;
+    replay.verified(true).await;
+    members(&replay).await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/verified/batch"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&replay.api)
+        .await;
+
+    replay.dispatch("verify_all").await.unwrap();
+
+    assert_eq!(
+        replay.replies().await,
+        ["Successfully completed re-verifications. Was able to verify 1 member."]
+    );
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(replay.ids.user, replay.ids.role)]
+    );
+}
+
+#[tokio::test]
+async fn reports_unregistered_guilds() {
+    let mut replay = Replay::start(Ids::new()).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", replay.ids.guild)))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&replay.api)
+        .await;
+
+    assert!(replay.dispatch("verify_all").await.is_err());
+
+    assert_eq!(
+        replay.replies().await,
+        ["It looks like your server doesn't support this bot, please run /setup."]
+    );
+    assert_eq!(replay.role_changes().await, []);
+}
+
+#[tokio::test]
+async fn batch_results_are_only_cached_for_their_guild() {
+    let replay = Replay::start(Ids::new()).await;
+    let api = state(&replay.ctx).await.api();
+    let user_id = UserId::new(replay.ids.user);
+    Mock::given(method("POST"))
+        .and(path("/api/v1/verified/batch"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "verified": [replay.ids.user.to_string()] })),
+        )
+        .mount(&replay.api)
+        .await;
+    replay.verified(false).await;
+
+    api::batch_verified(&api, &[user_id], GuildId::new(replay.ids.guild))
+        .await
+        .unwrap();
+
+    let elsewhere = GuildId::new(unique_id());
+    assert!(matches!(
+        api::is_verified(&api, user_id, elsewhere).await,
+        Err(ApiError::NotVerified)
+    ));
+}
+
+#[tokio::test]
+async fn gives_graduates_the_alumni_role() {
+    let mut replay = Replay::start(Ids::new()).await;
+    let ids = replay.ids;
+    let (alumni_role, alumnus) = (unique_id(), unique_id());
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/guild/{}", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "roleId": ids.role.to_string(),
+            "alumniRoleId": alumni_role.to_string(),
+            "approved": true,
+        })))
+        .mount(&replay.api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v10/guilds/{}/members", ids.guild)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            member(ids, ids.user, &[]),
+            member(ids, alumnus, &[alumni_role]),
+        ])))
+        .mount(&replay.discord)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/verified/batch"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "verified": [ids.user.to_string(), alumnus.to_string()] })),
+        )
+        .mount(&replay.api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/verified"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "verified": true,
+            "roleId": ids.role.to_string(),
+            "sotonLinkedDate": "2020-09-01T10:00:00.000Z",
+            "discordLinkedDate": "2020-09-01T10:01:00.000Z",
+            "status": "graduate",
+        })))
+        .mount(&replay.api)
+        .await;
+
+    replay.dispatch("verify_all").await.unwrap();
+
+    // The alumnus already has the alumni role so isn't given the verified role back.
+    assert_eq!(
+        replay.role_changes().await,
+        [RoleChange::Add(ids.user, alumni_role)]
+    );
+    let batch = replay
+        .api_requests()
+        .await
+        .into_iter()
+        .find(|r| r.url.path() == "/api/v1/verified/batch")
+        .unwrap();
+    assert_eq!(
+        batch.body_json::<serde_json::Value>().unwrap()["userIds"],
+        json!([ids.user.to_string()])
+    );
+}